@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, PartialEq)]
 pub struct ListFilmsRows {
     pub film_id: i32,
     pub title: String,
@@ -17,9 +18,11 @@ pub async fn list_films(
         })
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct FindFilmParams {
     pub eq_film_id: i32,
 }
+#[derive(Debug, Clone, PartialEq)]
 pub struct FindFilmRows {
     pub film_id: i32,
     pub title: String,
@@ -40,9 +43,11 @@ pub async fn find_film(
         })
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct CreateFilmParams {
     pub title: String,
 }
+#[derive(Debug, Clone, PartialEq)]
 pub struct CreateFilmRows {
     pub film_id: i32,
 }
@@ -61,10 +66,12 @@ pub async fn create_film(
         })
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct UpdateUserParams {
     pub eq_film_id: i32,
     pub set_title: String,
 }
+#[derive(Debug, Clone, PartialEq)]
 pub struct UpdateUserRows {
     pub film_id: i32,
     pub title: String,
@@ -88,9 +95,11 @@ pub async fn update_user(
         })
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct DeleteUserParams {
     pub eq_film_id: i32,
 }
+#[derive(Debug, Clone, PartialEq)]
 pub struct DeleteUserRows {
     pub film_id: i32,
     pub title: String,
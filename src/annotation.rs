@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use eyre::ContextCompat;
+use sqlparser::{
+    keywords::Keyword,
+    tokenizer::{Location, Token, Tokenizer, Whitespace},
+};
+
+/// Keys of the annotations understood by the generator
+const KEYS: &[&str] = &[
+    "json", "nullable", "not_null", "param", "derive", "returns", "stream", "batch", "copy",
+    "bulk", "channel",
+];
+
+/// `-- @key value` comments attached to a statement
+#[derive(Debug, Default, Clone)]
+pub struct Annotations(Vec<(String, String)>);
+
+impl Annotations {
+    pub(crate) fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.0
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Rust type declared with `-- @json name: path::To::Type`
+    pub(crate) fn json_type(&self, name: &str) -> Option<&str> {
+        self.get_all("json").find_map(|v| {
+            let (n, ty) = v.split_once(':')?;
+            (n.trim() == name).then(|| ty.trim())
+        })
+    }
+
+    /// Rejects unknown keys and annotations whose target is neither a column nor a parameter
+    pub(crate) fn check(&self, names: &[&str], params: usize) -> eyre::Result<()> {
+        for (key, value) in &self.0 {
+            if !KEYS.contains(&key.as_str()) {
+                eyre::bail!("unknown annotation @{key}");
+            }
+            let targets: Vec<&str> = match key.as_str() {
+                "json" => vec![
+                    value
+                        .split_once(':')
+                        .with_context(|| {
+                            format!("expected `@json name: Type`, got `@json {value}`")
+                        })?
+                        .0
+                        .trim(),
+                ],
+                "param" => vec![
+                    value
+                        .split_once(char::is_whitespace)
+                        .with_context(|| {
+                            format!("expected `@param $1 name`, got `@param {value}`")
+                        })?
+                        .0,
+                ],
+                "nullable" | "not_null" => list(value).collect(),
+                _ => continue,
+            };
+            for target in targets {
+                let found = match target.strip_prefix('$') {
+                    Some(i) => i.parse().is_ok_and(|i| (1..=params).contains(&i)),
+                    None => names.contains(&target.trim_start_matches([':', '@'])),
+                };
+                if !found {
+                    eyre::bail!("@{key} {target} matches no column or parameter");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Name given with `-- @param $1 name` or a named placeholder
+    pub(crate) fn param_name(&self, i: usize) -> Option<&str> {
+        let placeholder = format!("${i}");
+        self.get_all("param").find_map(|v| {
+            let (p, name) = v.split_once(char::is_whitespace)?;
+            (p == placeholder).then(|| name.trim())
+        })
+    }
+
+    /// Whether `$i`, or its explicit name, is listed in a `-- @key $1 $2` annotation
+    pub(crate) fn lists_param(&self, key: &str, i: usize) -> bool {
+        let placeholder = format!("${i}");
+        let name = self.param_name(i);
+        self.get_all(key).any(|v| {
+            list(v).any(|p| p == placeholder || Some(p.trim_start_matches([':', '@'])) == name)
+        })
+    }
+
+    fn push_comment(&mut self, comment: &str) {
+        for line in comment.lines() {
+            let Some(line) = line.trim().strip_prefix('@') else {
+                continue;
+            };
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            self.0.push((key.to_owned(), value.trim().to_owned()));
+        }
+    }
+}
+
+/// Rewrites the named placeholders (`:name` or `@name`) to `$n`, numbered after the
+/// highest explicit placeholder of the statement, and splits the comments of a sql file by
+/// statement, the comments after the last statement are returned as an extra element
+pub(crate) fn preprocess(stmts_raw: &str) -> eyre::Result<(String, Vec<Annotations>)> {
+    let tokens = Tokenizer::new(&sqlparser::dialect::PostgreSqlDialect {}, stmts_raw)
+        .tokenize_with_location()?;
+
+    // statement index of each token
+    let mut stmt_of = Vec::with_capacity(tokens.len());
+    let mut stmt = 0;
+    let mut has_statement = false;
+    for token in &tokens {
+        stmt_of.push(stmt);
+        match &token.token {
+            Token::Whitespace(_) => {}
+            Token::SemiColon if has_statement => {
+                stmt += 1;
+                has_statement = false;
+            }
+            Token::SemiColon => {}
+            _ => has_statement = true,
+        }
+    }
+
+    let mut last_placeholder = vec![0; stmt + 1];
+    for (token, &stmt) in tokens.iter().zip(&stmt_of) {
+        if let Token::Placeholder(p) = &token.token
+            && let Some(n) = p.strip_prefix('$').and_then(|n| n.parse::<usize>().ok())
+        {
+            last_placeholder[stmt] = last_placeholder[stmt].max(n);
+        }
+    }
+
+    let mut annotations = vec![Annotations::default(); stmt + 1];
+    let mut names = vec![HashMap::<String, usize>::new(); stmt + 1];
+    let mut replacements = Vec::new();
+    let mut prev: Option<&Token> = None;
+    let mut i = 0;
+    while i < tokens.len() {
+        let stmt = stmt_of[i];
+        match &tokens[i].token {
+            Token::Whitespace(Whitespace::SingleLineComment { comment, .. })
+            | Token::Whitespace(Whitespace::MultiLineComment(comment)) => {
+                annotations[stmt].push_comment(comment);
+            }
+            Token::Whitespace(_) => {}
+            Token::Colon | Token::AtSign if starts_operand(prev) => {
+                if let Some(Token::Word(w)) = tokens.get(i + 1).map(|t| &t.token)
+                    && w.quote_style.is_none()
+                {
+                    let n = *names[stmt].entry(w.value.clone()).or_insert_with(|| {
+                        last_placeholder[stmt] += 1;
+                        let n = last_placeholder[stmt];
+                        annotations[stmt]
+                            .0
+                            .push(("param".to_owned(), format!("${n} {}", w.value)));
+                        n
+                    });
+                    replacements.push((tokens[i].span.start, tokens[i + 1].span.end, n));
+                    prev = Some(&tokens[i + 1].token);
+                    i += 2;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        if !matches!(tokens[i].token, Token::Whitespace(_)) {
+            prev = Some(&tokens[i].token);
+        }
+        i += 1;
+    }
+    if has_statement {
+        // last statement without a trailing semicolon
+        annotations.push(Annotations::default());
+    }
+
+    let mut sql = stmts_raw.to_owned();
+    for (start, end, n) in replacements.into_iter().rev() {
+        let range = byte_offset(stmts_raw, start)..byte_offset(stmts_raw, end);
+        sql.replace_range(range, &format!("${n}"));
+    }
+
+    Ok((sql, annotations))
+}
+
+/// Whether a token can precede an operand, telling `:name` apart from array slices like
+/// `arr[lo:hi]` and `@name` apart from the operator
+fn starts_operand(prev: Option<&Token>) -> bool {
+    match prev {
+        Some(Token::Word(w)) => {
+            w.quote_style.is_none()
+                && matches!(
+                    w.keyword,
+                    Keyword::SELECT
+                        | Keyword::WHERE
+                        | Keyword::HAVING
+                        | Keyword::ON
+                        | Keyword::AND
+                        | Keyword::OR
+                        | Keyword::NOT
+                        | Keyword::IS
+                        | Keyword::IN
+                        | Keyword::LIKE
+                        | Keyword::ILIKE
+                        | Keyword::SIMILAR
+                        | Keyword::TO
+                        | Keyword::ESCAPE
+                        | Keyword::BETWEEN
+                        | Keyword::SYMMETRIC
+                        | Keyword::FROM
+                        | Keyword::VALUES
+                        | Keyword::CASE
+                        | Keyword::WHEN
+                        | Keyword::THEN
+                        | Keyword::ELSE
+                        | Keyword::LIMIT
+                        | Keyword::OFFSET
+                        | Keyword::RETURNING
+                )
+        }
+        // `ARRAY[:a]` and `arr[:i]`, a slice bound always follows a value
+        Some(
+            Token::LParen
+            | Token::LBracket
+            | Token::Comma
+            | Token::Eq
+            | Token::DoubleEq
+            | Token::Neq
+            | Token::Lt
+            | Token::Gt
+            | Token::LtEq
+            | Token::GtEq
+            | Token::Spaceship
+            | Token::Plus
+            | Token::Minus
+            | Token::Mul
+            | Token::Div
+            | Token::Mod
+            | Token::Caret
+            | Token::StringConcat
+            | Token::Ampersand
+            | Token::Pipe
+            | Token::Tilde
+            | Token::TildeAsterisk
+            | Token::ExclamationMarkTilde
+            | Token::ExclamationMarkTildeAsterisk
+            | Token::DoubleTilde
+            | Token::DoubleTildeAsterisk
+            | Token::ExclamationMarkDoubleTilde
+            | Token::ExclamationMarkDoubleTildeAsterisk
+            | Token::Arrow
+            | Token::LongArrow
+            | Token::HashArrow
+            | Token::HashLongArrow
+            | Token::HashMinus
+            | Token::AtArrow
+            | Token::ArrowAt
+            | Token::Question
+            | Token::QuestionAnd
+            | Token::QuestionPipe
+            | Token::Overlap,
+        ) => true,
+        _ => false,
+    }
+}
+
+fn byte_offset(raw: &str, location: Location) -> usize {
+    let line_start = raw
+        .split_inclusive('\n')
+        .take(location.line as usize - 1)
+        .map(str::len)
+        .sum::<usize>();
+    raw[line_start..]
+        .char_indices()
+        .nth(location.column as usize - 1)
+        .map_or(raw.len(), |(i, _)| line_start + i)
+}
+
+/// Items of a `-- @key a b` or `-- @key a, b` annotation
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|p| !p.is_empty())
+}
@@ -1,18 +1,39 @@
-use eyre::ContextCompat;
+use std::ops::ControlFlow;
+
 use sqlparser::ast::{
-    Assignment, AssignmentTarget, BinaryOperator, Expr, ObjectName, ObjectNamePart, SetExpr,
-    Statement, Value, ValueWithSpan,
+    Assignment, AssignmentTarget, BinaryOperator, Expr, FromTable, Function, FunctionArg,
+    FunctionArgExpr, FunctionArguments, Insert, ObjectName, ObjectNamePart, OnConflict,
+    OnConflictAction, OnInsert, Query, SelectItem, SetExpr, Statement, TableFactor, TableObject,
+    Value, ValueWithSpan, visit_expressions,
+};
+
+use crate::{
+    annotation::Annotations,
+    config::Config,
+    schema::{ConstraintKind, Schema},
 };
 
+/// Table column a value is read from or written to
+#[derive(Debug, Clone)]
+pub struct ColumnRef {
+    pub table: String,
+    pub column: String,
+}
+
 pub struct InputData {
     pub name: String,
     pub type_: tokio_postgres::types::Type,
+    pub is_nullable: bool,
+    pub source: Option<ColumnRef>,
 }
 #[derive(Debug)]
 pub struct ColumnData {
     pub name: String,
     pub type_: tokio_postgres::types::Type,
     pub is_nullable: bool,
+    pub source: Option<ColumnRef>,
+    /// Table or alias the column was qualified with, e.g. `f` in `f.name`
+    pub qualifier: Option<String>,
 }
 
 impl ColumnData {
@@ -21,6 +42,9 @@ impl ColumnData {
             name: self.name,
             type_,
             is_nullable: self.is_nullable,
+            // a cast value is no longer the column value
+            source: None,
+            qualifier: self.qualifier,
         }
     }
     pub(crate) fn with_name(self, name: String) -> Self {
@@ -28,96 +52,521 @@ impl ColumnData {
             name,
             type_: self.type_,
             is_nullable: self.is_nullable,
+            source: self.source,
+            // an alias is chosen by hand, so it's kept as is
+            qualifier: None,
         }
     }
 }
 
 pub enum ClientMethod {
+    /// Every row
     Query,
+    /// Exactly one row
+    QueryOne,
+    /// Zero or one row
+    QueryOpt,
     Execute,
 }
 
 pub struct PrepareStatement {
     pub name: String,
+    pub annotations: Annotations,
     pub statement: Box<Statement>,
     pub parameter_types: Vec<InputData>,
     pub result_types: Vec<ColumnData>,
+    /// Column names as postgres returns them, before any deduplication
+    pub column_names: Vec<String>,
     pub client_method: ClientMethod,
+    /// Rows are returned as the bare value of their single column
+    pub scalar: bool,
+    /// Binary COPY writing the same rows, for plain inserts
+    pub copy_in: Option<CopyIn>,
+    /// Rewritten by `@bulk` to insert the arrays of each parameter
+    pub bulk: bool,
+    /// Constraints the statement can violate
+    pub violations: Vec<Violation>,
+}
+
+/// Constraint checked when a statement writes its table
+pub struct Violation {
+    pub constraint: String,
+    pub kind: ConstraintKind,
+    /// Table the constraint is defined on
+    pub table: String,
+    pub columns: Vec<String>,
+    /// Foreign key of another table referencing the written one
+    pub referencing: bool,
+}
+
+/// `COPY t (cols) FROM STDIN BINARY` equivalent of an insert
+pub struct CopyIn {
+    pub statement: String,
+    /// Parameter written in each column
+    pub params: Vec<usize>,
 }
 
 pub(crate) async fn prepare_stmts(
     client: &impl tokio_postgres::GenericClient,
+    config: &Config,
     stmts_raw: &str,
 ) -> eyre::Result<Vec<PrepareStatement>> {
     let schema = crate::schema::load_schema(client).await?;
-    let stmts =
-        sqlparser::parser::Parser::parse_sql(&sqlparser::dialect::PostgreSqlDialect {}, stmts_raw)?;
+    let (stmts_raw, annotations) = crate::annotation::preprocess(stmts_raw)?;
+    let stmts = sqlparser::parser::Parser::parse_sql(
+        &sqlparser::dialect::PostgreSqlDialect {},
+        &stmts_raw,
+    )?;
 
-    let futs = stmts.into_iter().map(|stmt| async {
-        let Statement::Prepare {
-            name,
-            data_types: _,
-            statement,
-        } = stmt
-        else {
-            eyre::bail!("sql files should contains only prepare statements, found {stmt}");
-        };
-        let ps = client.prepare(&statement.to_string()).await?;
-        let result_types = crate::code_inference::infer_output(&statement, &schema)?;
-
-        debug_assert!(
-            result_types
-                .iter()
-                .zip(ps.columns())
-                .all(|(inferred, db)| inferred.type_ == *db.type_()),
-            "got: {:?}, expect: {:?}",
-            result_types,
-            ps.columns()
-        );
+    let futs = stmts
+        .into_iter()
+        .zip(annotations)
+        .map(|(stmt, annotations)| async {
+            let Statement::Prepare {
+                name,
+                data_types: _,
+                statement,
+            } = stmt
+            else {
+                eyre::bail!("sql files should contains only prepare statements, found {stmt}");
+            };
+            let ps = client.prepare(&statement.to_string()).await?;
+            let result_types =
+                dedup_column_names(crate::code_inference::infer_output(&statement, &schema)?)?;
+
+            debug_assert!(
+                result_types
+                    .iter()
+                    .zip(ps.columns())
+                    .all(|(inferred, db)| inferred.type_ == *db.type_()),
+                "got: {:?}, expect: {:?}",
+                result_types,
+                ps.columns()
+            );
 
-        Ok(PrepareStatement {
-            name: name.value,
-            client_method: calc_client_method(&ps, &statement),
-            parameter_types: ps
+            let names = param_names(&statement, &annotations, ps.params().len())?;
+            let parameter_types = ps
                 .params()
                 .iter()
+                .zip(names)
                 .enumerate()
-                .map(|(i, t)| {
+                .map(|(i, (t, name))| {
+                    let source = source_from_statement(&statement, i + 1);
+                    let is_nullable = if annotations.lists_param("nullable", i + 1) {
+                        true
+                    } else if annotations.lists_param("not_null", i + 1) {
+                        false
+                    } else {
+                        infer_param_nullable(&statement, i + 1, source.as_ref(), &schema)
+                    };
                     Ok(InputData {
-                        name: name_from_statement(&statement, i + 1)?.context("param not found")?,
+                        name,
                         type_: t.clone(),
+                        is_nullable,
+                        source,
                     })
                 })
-                .collect::<eyre::Result<_>>()?,
-            result_types,
-            statement,
-        })
-    });
+                .collect::<eyre::Result<_>>()?;
+
+            let infer = config.output.infer_returns;
+            let client_method = calc_client_method(&ps, &statement, &annotations, &schema, infer)?;
+            let scalar = calc_scalar(&result_types, &annotations, infer)?;
+            let bulk = annotations.get_all("bulk").next().is_some();
+            let statement = if bulk {
+                let statement = bulk_insert(&statement, ps.params())?;
+                // makes sure postgres accepts the rewritten statement
+                client.prepare(&statement.to_string()).await?;
+                statement
+            } else {
+                statement
+            };
+            let copy_in = copy_in(&statement, ps.params().len());
+            let violations = violations(&statement, &schema);
+            let ps = PrepareStatement {
+                name: name.value,
+                annotations,
+                client_method,
+                scalar,
+                parameter_types,
+                result_types,
+                column_names: ps.columns().iter().map(|c| c.name().to_owned()).collect(),
+                copy_in,
+                bulk,
+                violations,
+                statement,
+            };
+            check_annotations(&ps)?;
+            Ok(ps)
+        });
 
     futures::future::try_join_all(futs).await
 }
 
-fn calc_client_method(ps: &tokio_postgres::Statement, stmt: &Statement) -> ClientMethod {
-    match stmt {
+/// Annotations may target a parameter or a result column by name or by source column
+fn check_annotations(ps: &PrepareStatement) -> eyre::Result<()> {
+    let targets: Vec<&str> = ps
+        .parameter_types
+        .iter()
+        .map(|p| (&p.name, &p.source))
+        .chain(ps.result_types.iter().map(|c| (&c.name, &c.source)))
+        .flat_map(|(name, source)| {
+            std::iter::once(name.as_str()).chain(source.as_ref().map(|s| s.column.as_str()))
+        })
+        .collect();
+    ps.annotations.check(&targets, ps.parameter_types.len())
+}
+
+/// Parameter written in each column of `INSERT INTO t (a, b) VALUES ($1, $2)`, when every
+/// parameter is written once, as is
+fn plain_insert_params(insert: &Insert, params_len: usize) -> Option<Vec<usize>> {
+    let source = insert.source.as_ref()?;
+    let SetExpr::Values(values) = &*source.body else {
+        return None;
+    };
+    let [row] = values.rows.as_slice() else {
+        return None;
+    };
+    if source.with.is_some() || insert.columns.is_empty() || row.len() != insert.columns.len() {
+        return None;
+    }
+    let params = row
+        .iter()
+        .map(|e| match e {
+            Expr::Value(ValueWithSpan {
+                value: Value::Placeholder(p),
+                span: _,
+            }) => p.strip_prefix('$')?.parse::<usize>().ok()?.checked_sub(1),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let mut sorted = params.clone();
+    sorted.sort_unstable();
+    sorted.into_iter().eq(0..params_len).then_some(params)
+}
+
+/// Only plain inserts without `RETURNING` or `ON CONFLICT` can be replaced by a COPY
+fn copy_in(stmt: &Statement, params_len: usize) -> Option<CopyIn> {
+    let Statement::Insert(insert) = stmt else {
+        return None;
+    };
+    let TableObject::TableName(table) = &insert.table else {
+        return None;
+    };
+    if insert.on.is_some() || insert.returning.is_some() {
+        return None;
+    }
+    let params = plain_insert_params(insert, params_len)?;
+    let columns = insert
+        .columns
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(CopyIn {
+        statement: format!("COPY {table} ({columns}) FROM STDIN BINARY"),
+        params,
+    })
+}
+
+/// `-- @bulk` rewrites `INSERT ... VALUES ($1, $2)` into
+/// `INSERT ... SELECT * FROM unnest($1::t1[], $2::t2[])`, inserting every row in one statement
+fn bulk_insert(
+    stmt: &Statement,
+    params: &[tokio_postgres::types::Type],
+) -> eyre::Result<Box<Statement>> {
+    const USAGE: &str =
+        "@bulk needs an INSERT INTO t (cols) VALUES ($1, ...) writing each parameter once";
+    let Statement::Insert(insert) = stmt else {
+        eyre::bail!(USAGE);
+    };
+    let Some(columns) = plain_insert_params(insert, params.len()) else {
+        eyre::bail!(USAGE);
+    };
+    let arrays = columns
+        .iter()
+        .map(|&i| {
+            let ty = &params[i];
+            if matches!(ty.kind(), tokio_postgres::types::Kind::Array(_)) {
+                eyre::bail!("array parameter ${} can't be inserted with @bulk", i + 1);
+            }
+            Ok(if ty.schema() == "pg_catalog" {
+                format!("${}::{}[]", i + 1, ty.name())
+            } else {
+                format!("${}::\"{}\".\"{}\"[]", i + 1, ty.schema(), ty.name())
+            })
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let select = format!("SELECT * FROM unnest({})", arrays.join(", "));
+    let mut parser = sqlparser::parser::Parser::new(&sqlparser::dialect::PostgreSqlDialect {})
+        .try_with_sql(&select)?;
+    let mut insert = insert.clone();
+    insert.source = Some(parser.parse_query()?);
+    Ok(Box::new(Statement::Insert(insert)))
+}
+
+/// Constraints of the table written by an insert or an update, and foreign keys referencing
+/// the table updated or deleted from
+fn violations(stmt: &Statement, schema: &Schema) -> Vec<Violation> {
+    let (name, own, referenced) = match stmt {
+        Statement::Insert(insert) => match &insert.table {
+            TableObject::TableName(name) => (name, true, false),
+            _ => return Vec::new(),
+        },
+        Statement::Update { table, .. } => match &table.relation {
+            TableFactor::Table { name, .. } => (name, true, true),
+            _ => return Vec::new(),
+        },
+        Statement::Delete(delete) => {
+            let (FromTable::WithFromKeyword(tables) | FromTable::WithoutKeyword(tables)) =
+                &delete.from;
+            match tables.first().map(|t| &t.relation) {
+                Some(TableFactor::Table { name, .. }) => (name, false, true),
+                _ => return Vec::new(),
+            }
+        }
+        _ => return Vec::new(),
+    };
+    let Some(table) = name
+        .0
+        .last()
+        .and_then(ObjectNamePart::as_ident)
+        .and_then(|t| schema.find_table_by_name(&t.value))
+    else {
+        return Vec::new();
+    };
+    schema
+        .constraints
+        .iter()
+        .filter(|c| {
+            (own && c.table_oid == table.oid)
+                || (referenced && c.referenced_table_oid == Some(table.oid))
+        })
+        .filter_map(|c| {
+            Some(Violation {
+                constraint: c.name.clone(),
+                kind: c.kind,
+                table: schema.find_table_by_oid(c.table_oid)?.name.clone(),
+                columns: c.columns.clone(),
+                referencing: c.table_oid != table.oid,
+            })
+        })
+        .collect()
+}
+
+/// Prefixes result columns sharing a name with their table alias, e.g. `f_name` and `l_name`
+/// for `f.name, l.name`
+fn dedup_column_names(mut columns: Vec<ColumnData>) -> eyre::Result<Vec<ColumnData>> {
+    let count =
+        |columns: &[ColumnData], name: &str| columns.iter().filter(|c| c.name == name).count();
+
+    let duplicated = columns
+        .iter()
+        .map(|c| count(&columns, &c.name) > 1)
+        .collect::<Vec<_>>();
+    for (c, duplicated) in columns.iter_mut().zip(duplicated) {
+        if let (true, Some(qualifier)) = (duplicated, &c.qualifier) {
+            c.name = format!("{qualifier}_{}", c.name);
+        }
+    }
+
+    if let Some(c) = columns.iter().find(|c| count(&columns, &c.name) > 1) {
+        eyre::bail!(
+            "result column {} appears more than once, qualify it with the table alias or add an alias like `AS other_{}`",
+            c.name,
+            c.name
+        );
+    }
+    Ok(columns)
+}
+
+/// Words of `-- @returns one|opt|many struct|scalar`
+fn returns_annotation(annotations: &Annotations) -> eyre::Result<Vec<&str>> {
+    let words = annotations
+        .get_all("returns")
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>();
+    if let Some(w) = words
+        .iter()
+        .find(|w| !["one", "opt", "many", "struct", "scalar"].contains(w))
+    {
+        eyre::bail!("unknown @returns {w}, expected one, opt, many, struct or scalar");
+    }
+    Ok(words)
+}
+
+fn calc_client_method(
+    ps: &tokio_postgres::Statement,
+    stmt: &Statement,
+    annotations: &Annotations,
+    schema: &Schema,
+    infer: bool,
+) -> eyre::Result<ClientMethod> {
+    let returns = returns_annotation(annotations)?;
+    let method = match stmt {
         Statement::Delete(_) | Statement::Insert(_) | Statement::Update { .. }
             if ps.columns().is_empty() =>
         {
+            if !returns.is_empty() {
+                eyre::bail!("@returns on a statement without RETURNING");
+            }
             ClientMethod::Execute
         }
+        _ if returns.contains(&"one") => ClientMethod::QueryOne,
+        _ if returns.contains(&"opt") => ClientMethod::QueryOpt,
+        _ if returns.contains(&"many") => ClientMethod::Query,
+        Statement::Query(q) if infer && returns_single_row(q, schema) => ClientMethod::QueryOne,
         _ => ClientMethod::Query,
+    };
+    Ok(method)
+}
+
+/// A select without `FROM`, like `SELECT EXISTS(...)`, or only aggregating without
+/// `GROUP BY`, like `SELECT count(*) FROM films`, always returns one row, unless it calls a
+/// set-returning function like `generate_series`
+fn returns_single_row(q: &sqlparser::ast::Query, schema: &Schema) -> bool {
+    fn is_aggregate(expr: &Expr) -> bool {
+        match expr {
+            Expr::Function(f) => {
+                f.over.is_none()
+                    && matches!(
+                        f.name.to_string().to_lowercase().as_str(),
+                        "count"
+                            | "sum"
+                            | "avg"
+                            | "min"
+                            | "max"
+                            | "bool_and"
+                            | "bool_or"
+                            | "every"
+                            | "array_agg"
+                            | "string_agg"
+                            | "json_agg"
+                            | "jsonb_agg"
+                            | "json_object_agg"
+                            | "jsonb_object_agg"
+                    )
+            }
+            Expr::Cast { expr, .. } | Expr::Nested(expr) => is_aggregate(expr),
+            _ => false,
+        }
+    }
+
+    let SetExpr::Select(select) = &*q.body else {
+        return false;
+    };
+    if q.limit.is_some() || q.offset.is_some() || q.fetch.is_some() || select.having.is_some() {
+        return false;
+    }
+    let without_group_by = matches!(
+        &select.group_by,
+        sqlparser::ast::GroupByExpr::Expressions(exprs, _) if exprs.is_empty()
+    );
+    let aggregates = select.projection.iter().all(|si| match si {
+        SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+            is_aggregate(expr)
+        }
+        _ => false,
+    });
+    let returns_set = visit_expressions(&select.projection, |e| match e {
+        Expr::Function(f) if schema.is_set_returning_func(&f.name.to_string()) => {
+            ControlFlow::Break(())
+        }
+        _ => ControlFlow::Continue(()),
+    })
+    .is_break();
+    !returns_set
+        && ((select.from.is_empty() && select.selection.is_none())
+            || (without_group_by && aggregates))
+}
+
+fn calc_scalar(
+    result_types: &[ColumnData],
+    annotations: &Annotations,
+    infer: bool,
+) -> eyre::Result<bool> {
+    let returns = returns_annotation(annotations)?;
+    if returns.contains(&"struct") {
+        return Ok(false);
+    }
+    if returns.contains(&"scalar") {
+        if result_types.len() != 1 {
+            eyre::bail!(
+                "@returns scalar needs a single column, found {}",
+                result_types.len()
+            );
+        }
+        return Ok(true);
     }
+    Ok(infer && result_types.len() == 1)
 }
 
-fn name_from_statement(stmt: &Statement, i: usize) -> eyre::Result<Option<String>> {
+/// Names every parameter, explicit names must be unique while derived ones colliding with
+/// a previous name get a `_2`, `_3`... suffix
+fn param_names(
+    stmt: &Statement,
+    annotations: &Annotations,
+    len: usize,
+) -> eyre::Result<Vec<String>> {
+    let explicit = (1..=len)
+        .map(|i| annotations.param_name(i))
+        .collect::<Vec<_>>();
+    let mut used = std::collections::HashSet::new();
+    for (i, name) in explicit.iter().enumerate() {
+        if let Some(name) = name
+            && !used.insert(name.to_string())
+        {
+            let first = explicit.iter().position(|n| *n == Some(*name)).unwrap_or(i);
+            eyre::bail!(
+                "parameters ${} and ${} are both named {name}",
+                first + 1,
+                i + 1
+            );
+        }
+    }
+
+    Ok(explicit
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| match name {
+            Some(name) => name.to_owned(),
+            None => {
+                let name = name_from_statement(stmt, i + 1);
+                let mut candidate = name.clone();
+                let mut k = 2;
+                while !used.insert(candidate.clone()) {
+                    candidate = format!("{name}_{k}");
+                    k += 1;
+                }
+                candidate
+            }
+        })
+        .collect())
+}
+
+/// Names a parameter after where it's used, falling back to `p{i}`
+fn name_from_statement(stmt: &Statement, i: usize) -> String {
+    let name = match name_from_clauses(stmt, i) {
+        Ok(Some(name)) => Ok(Some(name)),
+        Ok(None) => name_from_any_expr(stmt, i),
+        Err(e) => name_from_any_expr(stmt, i).and_then(|n| n.ok_or(e).map(Some)),
+    };
+    match name {
+        Ok(Some(name)) => name,
+        Ok(None) => {
+            log::warn!("no name found for ${i} on {stmt}, using p{i}");
+            format!("p{i}")
+        }
+        Err(e) => {
+            log::warn!("failed to name ${i} on {stmt}, using p{i}: {e}");
+            format!("p{i}")
+        }
+    }
+}
+
+/// Positions that give a better name than the expression around the placeholder
+fn name_from_clauses(stmt: &Statement, i: usize) -> eyre::Result<Option<String>> {
     match stmt {
-        Statement::Query(q) => match *q.body {
-            SetExpr::Select(ref select) => select
-                .selection
-                .as_ref()
-                .and_then(|s| name_from_expr(s, i).transpose())
-                .transpose(),
-            _ => eyre::bail!("not supported yet"),
-        },
+        Statement::Query(q) => name_from_query(q, i),
         Statement::Delete(delete) => delete
             .selection
             .as_ref()
@@ -130,65 +579,243 @@ fn name_from_statement(stmt: &Statement, i: usize) -> eyre::Result<Option<String
         } => selection
             .as_ref()
             .and_then(|s| name_from_expr(s, i).transpose())
-            .or_else(|| {
-                assignments.iter().find_map(|a| {
-                    {
-                        let a: &Assignment = a;
-                        is_placehold(&a.value, i)
-                            .then(|| {
-                                Ok(match &a.target {
-                                    AssignmentTarget::ColumnName(ObjectName(os)) => {
-                                        std::iter::once("set")
-                                            .chain(os.iter().map(|o| match o {
-                                                ObjectNamePart::Identifier(ident) => {
-                                                    ident.value.as_str()
-                                                }
-                                            }))
-                                            .collect::<Vec<&str>>()
-                                            .join("_")
-                                    }
-                                    AssignmentTarget::Tuple(_) => {
-                                        eyre::bail!("{} with tuple is not supported yet", a.target)
-                                    }
-                                })
-                            })
-                            .transpose()
-                    }
-                    .transpose()
-                })
-            })
+            .or_else(|| name_from_assignments(assignments, i).transpose())
             .transpose(),
-        Statement::Insert(insert) => insert
-            .source
-            .as_ref()
-            .and_then(|q| {
-                match *q.body {
-                    SetExpr::Values(ref v) => Ok(v.rows.iter().find_map(|row| {
+        Statement::Insert(insert) => {
+            let from_source =
+                insert.source.as_ref().and_then(|q| match &*q.body {
+                    SetExpr::Values(v) => v.rows.iter().find_map(|row| {
                         row.iter()
                             .zip(&insert.columns)
                             .find_map(|(v, c)| is_placehold(v, i).then(|| c.value.clone()))
-                    })),
-                    _ => Err(eyre::eyre!("insert statemenr not found")),
-                }
-                .transpose()
-            })
-            .transpose(),
+                    }),
+                    // INSERT INTO t (a, b) SELECT $1, $2
+                    SetExpr::Select(select) => select
+                        .projection
+                        .iter()
+                        .zip(&insert.columns)
+                        .find_map(|(p, c)| {
+                            matches!(
+                            p,
+                            SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. }
+                                if wraps_placehold(e, i)
+                        )
+                        .then(|| c.value.clone())
+                        }),
+                    _ => None,
+                });
+            match from_source {
+                Some(name) => Ok(Some(name)),
+                None => match &insert.on {
+                    Some(OnInsert::OnConflict(OnConflict {
+                        action: OnConflictAction::DoUpdate(update),
+                        ..
+                    })) => name_from_assignments(&update.assignments, i),
+                    _ => Ok(None),
+                },
+            }
+        }
         _ => eyre::bail!("statement not supported"),
     }
 }
 
+fn name_from_query(q: &Query, i: usize) -> eyre::Result<Option<String>> {
+    if let SetExpr::Select(select) = &*q.body {
+        if let Some(name) = select
+            .selection
+            .as_ref()
+            .and_then(|s| name_from_expr(s, i).transpose())
+            .transpose()?
+        {
+            return Ok(Some(name));
+        }
+        // SELECT $1::int AS answer
+        if let Some(alias) = select.projection.iter().find_map(|p| match p {
+            SelectItem::ExprWithAlias { expr, alias } if wraps_placehold(expr, i) => Some(alias),
+            _ => None,
+        }) {
+            return Ok(Some(alias.value.clone()));
+        }
+    }
+    if q.limit.as_ref().is_some_and(|l| is_placehold(l, i)) {
+        return Ok(Some("limit".to_owned()));
+    }
+    if q.offset.as_ref().is_some_and(|o| is_placehold(&o.value, i)) {
+        return Ok(Some("offset".to_owned()));
+    }
+    Ok(None)
+}
+
+/// `SET col = $1` and `SET col = COALESCE($1, col)` are named `set_col`
+fn name_from_assignments(assignments: &[Assignment], i: usize) -> eyre::Result<Option<String>> {
+    assignments
+        .iter()
+        .find(|a| wraps_placehold(&a.value, i))
+        .map(|a| match &a.target {
+            AssignmentTarget::ColumnName(ObjectName(os)) => Ok(std::iter::once("set")
+                .chain(os.iter().map(|o| match o {
+                    ObjectNamePart::Identifier(ident) => ident.value.as_str(),
+                }))
+                .collect::<Vec<&str>>()
+                .join("_")),
+            AssignmentTarget::Tuple(_) => {
+                eyre::bail!("{} with tuple is not supported yet", a.target)
+            }
+        })
+        .transpose()
+}
+
+/// Searches every expression of the statement, like `JOIN ... ON`, `HAVING` or subqueries
+fn name_from_any_expr(stmt: &Statement, i: usize) -> eyre::Result<Option<String>> {
+    let mut error = None;
+    let found = visit_expressions(stmt, |e| match name_from_expr(e, i) {
+        Ok(Some(name)) => ControlFlow::Break(name),
+        Ok(None) => ControlFlow::Continue(()),
+        Err(err) => {
+            error.get_or_insert(err);
+            ControlFlow::Continue(())
+        }
+    });
+    match (found, error) {
+        (ControlFlow::Break(name), _) => Ok(Some(name)),
+        (ControlFlow::Continue(()), Some(err)) => Err(err),
+        (ControlFlow::Continue(()), None) => Ok(None),
+    }
+}
+
+/// Finds the column a placeholder is assigned to, used to apply per column overrides
+fn source_from_statement(stmt: &Statement, i: usize) -> Option<ColumnRef> {
+    let column_ref = |table: &ObjectName, column: &str| {
+        Some(ColumnRef {
+            table: table
+                .0
+                .last()
+                .and_then(ObjectNamePart::as_ident)?
+                .value
+                .clone(),
+            column: column.to_owned(),
+        })
+    };
+    match stmt {
+        Statement::Update {
+            table, assignments, ..
+        } => {
+            let TableFactor::Table { name, .. } = &table.relation else {
+                return None;
+            };
+            assignments.iter().find_map(|a| match &a.target {
+                AssignmentTarget::ColumnName(ObjectName(os)) if is_placehold(&a.value, i) => os
+                    .last()
+                    .and_then(ObjectNamePart::as_ident)
+                    .and_then(|c| column_ref(name, &c.value)),
+                _ => None,
+            })
+        }
+        Statement::Insert(insert) => {
+            let TableObject::TableName(name) = &insert.table else {
+                return None;
+            };
+            let SetExpr::Values(v) = &*insert.source.as_ref()?.body else {
+                return None;
+            };
+            v.rows.iter().find_map(|row| {
+                row.iter()
+                    .zip(&insert.columns)
+                    .find(|(v, _)| is_placehold(v, i))
+                    .and_then(|(_, c)| column_ref(name, &c.value))
+            })
+        }
+        _ => None,
+    }
+}
+
+/// A parameter accepts null when it is assigned to a nullable column or used where
+/// null is meaningful, like `IS NOT DISTINCT FROM $1` or `COALESCE($1, col)`
+fn infer_param_nullable(
+    stmt: &Statement,
+    i: usize,
+    source: Option<&ColumnRef>,
+    schema: &Schema,
+) -> bool {
+    let assigned_nullable = source
+        .and_then(|s| {
+            schema
+                .find_table_by_name(&s.table)?
+                .find_by_col_name(&s.column)
+        })
+        .is_some_and(|c| c.nullable);
+
+    let null_aware_usage = visit_expressions(stmt, |e| match e {
+        Expr::IsDistinctFrom(l, r) | Expr::IsNotDistinctFrom(l, r)
+            if is_placehold(l, i) || is_placehold(r, i) =>
+        {
+            ControlFlow::Break(())
+        }
+        Expr::Function(f)
+            if f.name.to_string().eq_ignore_ascii_case("coalesce")
+                && function_args(f).any(|a| is_placehold(a, i)) =>
+        {
+            ControlFlow::Break(())
+        }
+        _ => ControlFlow::Continue(()),
+    })
+    .is_break();
+
+    assigned_nullable || null_aware_usage
+}
+
 fn is_placehold(e: &Expr, i: usize) -> bool {
-    if let Expr::Value(ValueWithSpan {
-        value: Value::Placeholder(p),
-        span: _,
-    }) = e
-    {
-        *p == format!("${i}")
-    } else {
-        false
+    match e {
+        Expr::Value(ValueWithSpan {
+            value: Value::Placeholder(p),
+            span: _,
+        }) => *p == format!("${i}"),
+        // `$1::int` and `($1)` are still the placeholder
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => is_placehold(expr, i),
+        _ => false,
+    }
+}
+
+fn contains_placehold(e: &Expr, i: usize) -> bool {
+    visit_expressions(e, |e| {
+        if is_placehold(e, i) {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })
+    .is_break()
+}
+
+/// The placeholder itself or wrapped by a function, like `lower($1)`, in both cases the
+/// parameter is named after the expression it's compared to
+fn wraps_placehold(e: &Expr, i: usize) -> bool {
+    match e {
+        Expr::Function(f) => function_args(f).any(|a| wraps_placehold(a, i)),
+        Expr::UnaryOp { expr, .. } => wraps_placehold(expr, i),
+        _ => is_placehold(e, i),
     }
 }
 
+fn function_args(f: &Function) -> impl Iterator<Item = &Expr> {
+    let args = match &f.args {
+        FunctionArguments::List(al) => al.args.as_slice(),
+        FunctionArguments::None | FunctionArguments::Subquery(_) => &[],
+    };
+    args.iter().filter_map(|a| match a {
+        FunctionArg::Named {
+            arg: FunctionArgExpr::Expr(e),
+            ..
+        }
+        | FunctionArg::ExprNamed {
+            arg: FunctionArgExpr::Expr(e),
+            ..
+        }
+        | FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) => Some(e),
+        _ => None,
+    })
+}
+
 fn name_from_expr(expr: &Expr, i: usize) -> eyre::Result<Option<String>> {
     fn name_expr(e: &Expr) -> eyre::Result<String> {
         Ok(match e {
@@ -198,43 +825,245 @@ fn name_from_expr(expr: &Expr, i: usize) -> eyre::Result<Option<String>> {
                 .collect::<Vec<_>>()
                 .join("_"),
             Expr::Identifier(ident) => ident.value.to_owned(),
+            Expr::Function(f) => std::iter::once(Ok(f.name.to_string()))
+                .chain(
+                    function_args(f)
+                        .filter(|a| !matches!(a, Expr::Value(_)))
+                        .map(name_expr),
+                )
+                .collect::<eyre::Result<Vec<_>>>()?
+                .join("_"),
+            Expr::Cast { expr, .. } | Expr::Nested(expr) => name_expr(expr)?,
+            Expr::BinaryOp {
+                left,
+                op:
+                    BinaryOperator::Arrow
+                    | BinaryOperator::LongArrow
+                    | BinaryOperator::HashArrow
+                    | BinaryOperator::HashLongArrow,
+                right,
+            } => format!("{}_{}", name_expr(left)?, name_expr(right)?),
+            Expr::BinaryOp { left, op, right } => {
+                format!(
+                    "{}_{}_{}",
+                    name_expr(left)?,
+                    name_op(op)?,
+                    name_expr(right)?
+                )
+            }
+            Expr::Value(v) => match &v.value {
+                Value::SingleQuotedString(s) | Value::Number(s, _) => s
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("_"),
+                _ => eyre::bail!("{e} not supported yet"),
+            },
             _ => eyre::bail!("{e} not supported yet"),
         })
     }
-    fn name_op(op: &sqlparser::ast::BinaryOperator) -> eyre::Result<&str> {
+    fn name_op(op: &BinaryOperator) -> eyre::Result<&str> {
         Ok(match op {
             BinaryOperator::Eq => "eq",
-            BinaryOperator::PGLikeMatch => "like",
+            BinaryOperator::NotEq => "ne",
             BinaryOperator::Gt => "gt",
             BinaryOperator::Lt => "lt",
             BinaryOperator::GtEq => "ge",
             BinaryOperator::LtEq => "le",
+            BinaryOperator::PGLikeMatch => "like",
+            BinaryOperator::PGILikeMatch => "ilike",
+            BinaryOperator::PGNotLikeMatch => "not_like",
+            BinaryOperator::PGNotILikeMatch => "not_ilike",
+            BinaryOperator::PGRegexMatch => "match",
+            BinaryOperator::PGRegexIMatch => "imatch",
+            BinaryOperator::PGRegexNotMatch => "not_match",
+            BinaryOperator::PGRegexNotIMatch => "not_imatch",
+            BinaryOperator::PGStartsWith => "starts_with",
+            BinaryOperator::Plus => "plus",
+            BinaryOperator::Minus => "minus",
+            BinaryOperator::Multiply => "mul",
+            BinaryOperator::Divide => "div",
+            BinaryOperator::Modulo => "mod",
+            BinaryOperator::StringConcat => "concat",
+            BinaryOperator::And => "and",
+            BinaryOperator::Or => "or",
+            BinaryOperator::AtArrow => "contains",
+            BinaryOperator::ArrowAt => "contained_by",
+            BinaryOperator::PGOverlap => "overlap",
+            BinaryOperator::Question => "has_key",
+            BinaryOperator::QuestionAnd => "has_all_keys",
+            BinaryOperator::QuestionPipe => "has_any_key",
+            BinaryOperator::Arrow | BinaryOperator::LongArrow => "key",
+            BinaryOperator::HashArrow | BinaryOperator::HashLongArrow => "path",
             _ => eyre::bail!("op {op} not supported yet"),
         })
     }
+    fn negate(name: &str, negated: bool) -> String {
+        if negated {
+            format!("not_{name}")
+        } else {
+            name.to_owned()
+        }
+    }
+    /// `x = ANY($1)` is named `any_x`, other operators are kept like `gt_any_x`
+    fn quantified(name: &str, op: &BinaryOperator) -> eyre::Result<String> {
+        Ok(match op {
+            BinaryOperator::Eq => name.to_owned(),
+            op => format!("{}_{name}", name_op(op)?),
+        })
+    }
+    fn first_name<'a>(
+        exprs: impl IntoIterator<Item = &'a Expr>,
+        i: usize,
+    ) -> eyre::Result<Option<String>> {
+        exprs
+            .into_iter()
+            .find_map(|e| name_from_expr(e, i).transpose())
+            .transpose()
+    }
+    /// Boolean operators join predicates, a wrapped placeholder is named by its own predicate
+    fn operand_placehold(op: &BinaryOperator, e: &Expr, i: usize) -> bool {
+        match op {
+            BinaryOperator::And | BinaryOperator::Or => is_placehold(e, i),
+            _ => wraps_placehold(e, i),
+        }
+    }
+    let named = |prefix: &str, e: &Expr| Ok(Some(format!("{prefix}_{}", name_expr(e)?)));
+
+    if !contains_placehold(expr, i) {
+        return Ok(None);
+    }
     match expr {
-        Expr::Identifier(_) | Expr::Value(_) => Ok(None),
-        Expr::BinaryOp { left, op, right } if is_placehold(&left, i) => {
-            Ok(Some(format!("{}_{}", name_op(op)?, name_expr(&right)?)))
+        Expr::BinaryOp { left, op, right } if operand_placehold(op, right, i) => {
+            named(name_op(op)?, left)
         }
-        Expr::BinaryOp { left, op, right } if is_placehold(right, i) => {
-            Ok(Some(format!("{}_{}", name_op(op)?, name_expr(&left)?)))
+        Expr::BinaryOp { left, op, right } if operand_placehold(op, left, i) => {
+            named(name_op(op)?, right)
         }
-        Expr::BinaryOp { left, op: _, right } => name_from_expr(&left, i)
-            .transpose()
-            .or_else(|| name_from_expr(&right, i).transpose())
-            .transpose(),
+        Expr::BinaryOp { left, op: _, right } => first_name([&**left, right], i),
         Expr::Like {
-            negated: _,
-            any: _,
+            negated,
+            expr,
+            pattern,
+            ..
+        } if wraps_placehold(pattern, i) => named(&negate("like", *negated), expr),
+        Expr::ILike {
+            negated,
             expr,
             pattern,
-            escape_char: _,
-        } if is_placehold(&pattern, i) => Ok(Some(format!(
-            "{}_{}",
-            name_op(&BinaryOperator::PGLikeMatch)?,
-            name_expr(&expr)?
+            ..
+        } if wraps_placehold(pattern, i) => named(&negate("ilike", *negated), expr),
+        Expr::SimilarTo {
+            negated,
+            expr,
+            pattern,
+            ..
+        } if wraps_placehold(pattern, i) => named(&negate("similar_to", *negated), expr),
+        Expr::Like { expr, pattern, .. }
+        | Expr::ILike { expr, pattern, .. }
+        | Expr::SimilarTo { expr, pattern, .. } => first_name([&**expr, pattern], i),
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => match list.iter().position(|e| wraps_placehold(e, i)) {
+            Some(_) if list.len() == 1 => named(&negate("in", *negated), expr),
+            Some(pos) => Ok(Some(format!(
+                "{}_{}_{}",
+                negate("in", *negated),
+                name_expr(expr)?,
+                pos + 1
+            ))),
+            None => first_name(std::iter::once(&**expr).chain(list), i),
+        },
+        Expr::AnyOp {
+            left,
+            compare_op,
+            right,
+            ..
+        } if wraps_placehold(right, i) => named(&quantified("any", compare_op)?, left),
+        Expr::AnyOp {
+            left,
+            compare_op,
+            right,
+            ..
+        } if wraps_placehold(left, i) => named(&quantified("any", compare_op)?, right),
+        Expr::AllOp {
+            left,
+            compare_op,
+            right,
+        } if wraps_placehold(right, i) => named(&quantified("all", compare_op)?, left),
+        Expr::AllOp {
+            left,
+            compare_op,
+            right,
+        } if wraps_placehold(left, i) => named(&quantified("all", compare_op)?, right),
+        Expr::AnyOp { left, right, .. } | Expr::AllOp { left, right, .. } => {
+            first_name([&**left, right], i)
+        }
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high: _,
+        } if wraps_placehold(low, i) => Ok(Some(format!(
+            "{}_{}_low",
+            negate("between", *negated),
+            name_expr(expr)?
         ))),
+        Expr::Between {
+            expr,
+            negated,
+            low: _,
+            high,
+        } if wraps_placehold(high, i) => Ok(Some(format!(
+            "{}_{}_high",
+            negate("between", *negated),
+            name_expr(expr)?
+        ))),
+        Expr::Between {
+            expr, low, high, ..
+        } => first_name([&**expr, low, high], i),
+        Expr::IsDistinctFrom(left, right) | Expr::IsNotDistinctFrom(left, right) => {
+            let prefix = negate("distinct", matches!(expr, Expr::IsNotDistinctFrom(_, _)));
+            if wraps_placehold(right, i) {
+                named(&prefix, left)
+            } else if wraps_placehold(left, i) {
+                named(&prefix, right)
+            } else {
+                first_name([&**left, right], i)
+            }
+        }
+        Expr::Function(f) => {
+            let args = function_args(f).collect::<Vec<_>>();
+            if args.iter().any(|a| wraps_placehold(a, i)) {
+                Ok(Some(
+                    std::iter::once(Ok(f.name.to_string()))
+                        .chain(
+                            args.into_iter()
+                                .filter(|a| {
+                                    !contains_placehold(a, i) && !matches!(a, Expr::Value(_))
+                                })
+                                .map(name_expr),
+                        )
+                        .collect::<eyre::Result<Vec<_>>>()?
+                        .join("_"),
+                ))
+            } else {
+                first_name(args, i)
+            }
+        }
+        Expr::Nested(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::UnaryOp { expr, .. }
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::IsTrue(expr)
+        | Expr::IsNotTrue(expr)
+        | Expr::IsFalse(expr)
+        | Expr::IsNotFalse(expr)
+        | Expr::IsUnknown(expr)
+        | Expr::IsNotUnknown(expr) => name_from_expr(expr, i),
         _ => eyre::bail!("{expr} not supported yet"),
     }
 }
@@ -2,22 +2,375 @@ use convert_case::{Case, Casing};
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 
-use crate::code_analysis::PrepareStatement;
+use crate::{
+    annotation::Annotations,
+    code_analysis::{ClientMethod, ColumnRef, PrepareStatement, Violation},
+    config::{Backend, Config, ParamsMode},
+    schema::ConstraintKind,
+};
+
+/// Items generated once per file, when one of the statements needs them
+#[derive(Default)]
+struct Helpers {
+    /// `json_serde` module
+    json_serde: bool,
+    /// `CopyInClient` trait
+    copy_in_client: bool,
+}
+
+/// Rust type of a generated field
+struct FieldType {
+    ty: TokenStream,
+    /// Wrapped in `Json`, which (de)serializes with serde but doesn't implement its traits
+    json: bool,
+    /// Borrows for `'a` from the caller
+    borrowed: bool,
+}
+
+/// Tokens that differ between the async and the blocking client
+struct Client {
+    /// `tokio_postgres`, `postgres` or `sqlx`
+    pg: TokenStream,
+    /// Paths of the `Json` wrapper and of `Oid`
+    json: TokenStream,
+    oid: TokenStream,
+    /// Client argument of every generated function
+    client_param: TokenStream,
+    asyncness: TokenStream,
+    await_: TokenStream,
+}
+
+impl Client {
+    fn new(backend: Backend) -> Self {
+        match backend {
+            Backend::TokioPostgres => Self {
+                pg: quote! { tokio_postgres },
+                json: quote! { tokio_postgres::types::Json },
+                oid: quote! { tokio_postgres::types::Oid },
+                client_param: quote! { c: &impl tokio_postgres::GenericClient },
+                asyncness: quote! { async },
+                await_: quote! { .await },
+            },
+            Backend::Postgres => Self {
+                pg: quote! { postgres },
+                json: quote! { postgres::types::Json },
+                oid: quote! { postgres::types::Oid },
+                client_param: quote! { c: &mut impl postgres::GenericClient },
+                asyncness: quote! {},
+                await_: quote! {},
+            },
+            Backend::Sqlx => Self {
+                pg: quote! { sqlx },
+                json: quote! { sqlx::types::Json },
+                oid: quote! { sqlx::postgres::types::Oid },
+                client_param: quote! { c: impl sqlx::PgExecutor<'_> },
+                asyncness: quote! { async },
+                await_: quote! { .await },
+            },
+        }
+    }
+}
+
+/// Pieces of a statement shared by the functions generated for it
+struct FnParts {
+    fn_name: proc_macro2::Ident,
+    /// `s: &Statements,` when the statements are prepared once
+    statements_param: TokenStream,
+    /// `p: FooParams,` when the statement has parameters
+    param_params: TokenStream,
+    params_struct_ident: proc_macro2::Ident,
+    /// `FooParams` borrows from the caller, `FooParams<'a>`
+    has_lifetime: bool,
+    /// Sql string, or the field of `Statements`
+    statement: TokenStream,
+    /// `&p.field` of every parameter
+    param_refs: Vec<TokenStream>,
+    /// Parameters of the client methods, with their types for `query_typed`
+    param_binding: TokenStream,
+    /// `params` array of `query_raw`
+    raw_params: TokenStream,
+    /// Rows struct, or the type of a scalar
+    row_type: TokenStream,
+    /// `Vec`, `Option` or single `row_type`, or the affected rows
+    return_type: TokenStream,
+    /// Builds a `row_type` from the row `r`
+    row_expr: TokenStream,
+    /// Whether the rows are also returned one by one, `*_stream` or `*_iter`
+    stream: bool,
+    /// Whether to also generate `*_batch`
+    batch: bool,
+    /// Binary COPY of a plain insert, for `*_copy`
+    copy_in: Option<CopyFn>,
+    /// `*Error` of the constraints the statement can violate, returned by the main function
+    error_ident: Option<proc_macro2::Ident>,
+}
+
+/// Binary COPY writing the rows of a plain insert
+struct CopyFn {
+    /// `COPY t (cols) FROM STDIN BINARY`
+    statement: String,
+    /// `Type` of each copied column
+    types: Vec<TokenStream>,
+    /// `&p.field` written in each column
+    refs: Vec<TokenStream>,
+}
 
 pub(crate) async fn gen_file(
     client: &impl tokio_postgres::GenericClient,
+    config: &Config,
     stmts_raw: String,
 ) -> eyre::Result<String> {
-    crate::code_analysis::prepare_stmts(client, &stmts_raw)
-        .await?
+    let stmts = crate::code_analysis::prepare_stmts(client, config, &stmts_raw).await?;
+    let statements = config
+        .output
+        .statements
+        .then(|| gen_statements(config, &stmts))
+        .transpose()?;
+    // channels can be declared anywhere, even after the last statement
+    let (_, annotations) = crate::annotation::preprocess(&stmts_raw)?;
+    let channels = gen_channels(config, &annotations)?;
+    let mut helpers = Helpers::default();
+    let mut code = stmts
         .into_iter()
-        .map(gen_fn)
-        .collect::<eyre::Result<Vec<String>>>()
-        .map(|s| s.join("\n"))
+        .map(|ps| gen_fn(config, ps, &mut helpers))
+        .collect::<eyre::Result<Vec<String>>>()?;
+    code.extend(statements);
+    code.extend(channels);
+    if helpers.json_serde {
+        code.push(gen_json_serde(config)?);
+    }
+    if helpers.copy_in_client {
+        code.push(gen_copy_in_client()?);
+    }
+    let code = code.join("\n");
+
+    Ok(match &config.output.header {
+        Some(header) => format!("{header}\n{code}"),
+        None => code,
+    })
 }
 
-fn gen_fn(ps: PrepareStatement) -> eyre::Result<String> {
-    fn quote_type(ty: &tokio_postgres::types::Type) -> eyre::Result<TokenStream> {
+/// `Statements` holding every statement of the file prepared on a connection, so they are
+/// parsed and planned once instead of on every call
+fn gen_statements(config: &Config, stmts: &[PrepareStatement]) -> eyre::Result<String> {
+    let Client {
+        pg,
+        client_param,
+        asyncness,
+        await_,
+        ..
+    } = Client::new(config.backend);
+    let fields = stmts
+        .iter()
+        .map(|ps| field_ident(&ps.name))
+        .collect::<Vec<_>>();
+    let sql_statements = stmts.iter().map(|ps| ps.statement.to_string());
+    let paragraph = quote! {
+        #[derive(Debug, Clone)]
+        pub struct Statements {
+            #(pub #fields: #pg::Statement,)*
+        }
+
+        impl Statements {
+            /// Prepared statements are bound to the connection that prepared them
+            pub #asyncness fn prepare(
+                #client_param,
+            ) -> Result<Self, #pg::Error> {
+                Ok(Self {
+                    #(#fields: c.prepare(#sql_statements)#await_?,)*
+                })
+            }
+        }
+    };
+
+    Ok(prettyplease::unparse(&syn::parse2(paragraph)?))
+}
+
+/// `-- @channel name: json path::To::Type`, or `text`, declares a LISTEN/NOTIFY channel with
+/// functions to notify, listen and read its payloads
+fn gen_channels(config: &Config, annotations: &[Annotations]) -> eyre::Result<Option<String>> {
+    let channels = annotations
+        .iter()
+        .flat_map(|a| a.get_all("channel"))
+        .collect::<Vec<_>>();
+    if channels.is_empty() {
+        return Ok(None);
+    }
+    if config.backend != Backend::TokioPostgres {
+        eyre::bail!("@channel needs the tokio-postgres backend");
+    }
+
+    let mut names = Vec::with_capacity(channels.len());
+    let fns = channels
+        .into_iter()
+        .map(|channel| {
+            let Some((name, payload)) = channel.split_once(':') else {
+                eyre::bail!("@channel {channel} should be `name: json path::To::Type` or `name: text`");
+            };
+            let name = name.trim();
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            {
+                eyre::bail!("channel {name} should be a lowercase sql identifier");
+            }
+            if names.contains(&name) {
+                eyre::bail!("channel {name} is declared more than once");
+            }
+            names.push(name);
+
+            // json is sent as text, `pg_notify` only takes text payloads
+            let (payload_type, payload_arg, notify_sql, item_type, payload_expr) =
+                match payload.split_whitespace().collect::<Vec<_>>().as_slice() {
+                    ["text"] => (
+                        quote! { &str },
+                        quote! { &payload },
+                        format!("SELECT pg_notify('{name}', $1)"),
+                        quote! { String },
+                        quote! { n.payload().to_owned() },
+                    ),
+                    ["json", path] => {
+                        let path = path
+                            .parse::<TokenStream>()
+                            .map_err(|e| eyre::eyre!("invalid channel type {path}: {e}"))?;
+                        (
+                            quote! { &#path },
+                            quote! { &tokio_postgres::types::Json(payload) },
+                            format!("SELECT pg_notify('{name}', $1::json::text)"),
+                            quote! { Result<#path, serde_json::Error> },
+                            quote! { serde_json::from_str(n.payload()) },
+                        )
+                    }
+                    _ => eyre::bail!(
+                        "@channel {channel} should be `name: json path::To::Type` or `name: text`"
+                    ),
+                };
+            let listen_sql = format!("LISTEN {name}");
+            let notify_fn_name = field_ident(&format!("notify_{name}"));
+            let listen_fn_name = field_ident(&format!("listen_{name}"));
+            let notifications_fn_name = field_ident(&format!("{name}_notifications"));
+            Ok(quote! {
+                pub async fn #notify_fn_name(
+                    c: &impl tokio_postgres::GenericClient,
+                    payload: #payload_type,
+                ) -> Result<(), tokio_postgres::Error> {
+                    c.execute(#notify_sql, &[#payload_arg]).await.map(|_| ())
+                }
+                pub async fn #listen_fn_name(
+                    c: &impl tokio_postgres::GenericClient,
+                ) -> Result<(), tokio_postgres::Error> {
+                    c.batch_execute(#listen_sql).await
+                }
+                /// Payloads sent to the channel, out of the messages polled from the connection
+                pub fn #notifications_fn_name(
+                    messages: impl futures::Stream<Item = tokio_postgres::AsyncMessage>,
+                ) -> impl futures::Stream<Item = #item_type> {
+                    futures::StreamExt::filter_map(messages, |m| async move {
+                        match m {
+                            tokio_postgres::AsyncMessage::Notification(n) if n.channel() == #name => {
+                                Some(#payload_expr)
+                            }
+                            _ => None,
+                        }
+                    })
+                }
+            })
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    Ok(Some(prettyplease::unparse(&syn::parse2(
+        quote! { #(#fns)* },
+    )?)))
+}
+
+/// `json_serde` (de)serializing the `Json<T>` fields of the serde derives through the
+/// wrapped value
+fn gen_json_serde(config: &Config) -> eyre::Result<String> {
+    let Client { json, .. } = Client::new(config.backend);
+    let paragraph = quote! {
+        mod json_serde {
+            pub fn serialize<T: serde::Serialize, S: serde::Serializer>(
+                v: &#json<T>,
+                s: S,
+            ) -> Result<S::Ok, S::Error> {
+                serde::Serialize::serialize(&v.0, s)
+            }
+
+            pub fn deserialize<'de, T: serde::Deserialize<'de>, D: serde::Deserializer<'de>>(
+                d: D,
+            ) -> Result<#json<T>, D::Error> {
+                T::deserialize(d).map(#json)
+            }
+
+            pub mod option {
+                pub fn serialize<T: serde::Serialize, S: serde::Serializer>(
+                    v: &Option<#json<T>>,
+                    s: S,
+                ) -> Result<S::Ok, S::Error> {
+                    serde::Serialize::serialize(&v.as_ref().map(|v| &v.0), s)
+                }
+
+                pub fn deserialize<'de, T: serde::Deserialize<'de>, D: serde::Deserializer<'de>>(
+                    d: D,
+                ) -> Result<Option<#json<T>>, D::Error> {
+                    <Option<T> as serde::Deserialize>::deserialize(d)
+                        .map(|v| v.map(#json))
+                }
+            }
+        }
+    };
+
+    Ok(prettyplease::unparse(&syn::parse2(paragraph)?))
+}
+
+/// `CopyInClient` taking a `tokio_postgres::Client` or `Transaction`, whose `copy_in` isn't
+/// part of `GenericClient`
+fn gen_copy_in_client() -> eyre::Result<String> {
+    let copy_in = quote! {
+        async fn binary_copy_in(
+            &self,
+            statement: &str,
+            types: &[tokio_postgres::types::Type],
+        ) -> Result<tokio_postgres::binary_copy::BinaryCopyInWriter, tokio_postgres::Error> {
+            let sink = self.copy_in(statement).await?;
+            Ok(tokio_postgres::binary_copy::BinaryCopyInWriter::new(sink, types))
+        }
+    };
+    let paragraph = quote! {
+        /// `tokio_postgres::Client` or `Transaction`, `GenericClient` doesn't expose `copy_in`
+        pub trait CopyInClient {
+            fn binary_copy_in(
+                &self,
+                statement: &str,
+                types: &[tokio_postgres::types::Type],
+            ) -> impl std::future::Future<
+                Output = Result<
+                    tokio_postgres::binary_copy::BinaryCopyInWriter,
+                    tokio_postgres::Error,
+                >,
+            > + Send;
+        }
+
+        impl CopyInClient for tokio_postgres::Client {
+            #copy_in
+        }
+
+        impl CopyInClient for tokio_postgres::Transaction<'_> {
+            #copy_in
+        }
+    };
+
+    Ok(prettyplease::unparse(&syn::parse2(paragraph)?))
+}
+
+fn gen_fn(config: &Config, ps: PrepareStatement, helpers: &mut Helpers) -> eyre::Result<String> {
+    let Client { pg, json, oid, .. } = Client::new(config.backend);
+
+    fn quote_type(
+        ty: &tokio_postgres::types::Type,
+        oid: &TokenStream,
+    ) -> eyre::Result<TokenStream> {
         use tokio_postgres::types::Type;
         Ok(match ty {
             &Type::BOOL => quote! { bool },
@@ -28,46 +381,249 @@ fn gen_fn(ps: PrepareStatement) -> eyre::Result<String> {
             &Type::FLOAT8 => quote! { f64 },
             &Type::CHAR | &Type::VARCHAR | &Type::TEXT | &Type::NAME => quote! { String },
             &Type::BYTEA => quote! { Vec<u8> },
-            &Type::OID => quote! { tokio_postgres::types::Oid },
-            &Type::OID_ARRAY => quote! { Vec<tokio_postgres::types::Oid> },
+            &Type::OID => quote! { #oid },
+            &Type::OID_ARRAY => quote! { Vec<#oid> },
             &Type::INT2_ARRAY => quote! { Vec<i16> },
+            &Type::INT4_ARRAY => quote! { Vec<i32> },
+            &Type::INT8_ARRAY => quote! { Vec<i64> },
+            &Type::FLOAT4_ARRAY => quote! { Vec<f32> },
+            &Type::FLOAT8_ARRAY => quote! { Vec<f64> },
             &Type::BOOL_ARRAY => quote! { Vec<bool> },
-            &Type::NAME_ARRAY => quote! { Vec<String> },
+            &Type::CHAR_ARRAY | &Type::VARCHAR_ARRAY | &Type::TEXT_ARRAY | &Type::NAME_ARRAY => {
+                quote! { Vec<String> }
+            }
+            &Type::JSON | &Type::JSONB => quote! { serde_json::Value },
             _ => eyre::bail!("type {ty} not supported yet"),
         })
     }
 
+    /// Parameter types borrowing from the caller, the ones that allocate when owned
+    fn quote_borrowed_type(
+        ty: &tokio_postgres::types::Type,
+        oid: &TokenStream,
+    ) -> eyre::Result<Option<TokenStream>> {
+        use tokio_postgres::types::Type;
+        Ok(Some(match ty {
+            &Type::CHAR | &Type::VARCHAR | &Type::TEXT | &Type::NAME => quote! { &'a str },
+            &Type::BYTEA => quote! { &'a [u8] },
+            &Type::CHAR_ARRAY | &Type::VARCHAR_ARRAY | &Type::TEXT_ARRAY | &Type::NAME_ARRAY => {
+                quote! { &'a [&'a str] }
+            }
+            &Type::OID_ARRAY
+            | &Type::INT2_ARRAY
+            | &Type::INT4_ARRAY
+            | &Type::INT8_ARRAY
+            | &Type::FLOAT4_ARRAY
+            | &Type::FLOAT8_ARRAY
+            | &Type::BOOL_ARRAY => {
+                let tokio_postgres::types::Kind::Array(member) = ty.kind() else {
+                    unreachable!("array types")
+                };
+                let elem = quote_type(member, oid)?;
+                quote! { &'a [#elem] }
+            }
+            &Type::JSON | &Type::JSONB => quote! { &'a serde_json::Value },
+            _ => return Ok(None),
+        }))
+    }
+
+    let quote_field_type =
+        |ty: &tokio_postgres::types::Type, source: Option<&ColumnRef>, name: &str, borrowed| {
+            use tokio_postgres::types::Type;
+            let is_json = matches!(*ty, Type::JSON | Type::JSONB);
+            let json_annotation = ps
+                .annotations
+                .json_type(name)
+                .or_else(|| source.and_then(|s| ps.annotations.json_type(&s.column)));
+            if json_annotation.is_some() && !is_json {
+                eyre::bail!("@json {name} is annotated but has type {ty}");
+            }
+            let override_ = json_annotation
+                .or_else(|| source.and_then(|s| config.column_override(&s.table, &s.column)))
+                .or_else(|| config.type_override(ty));
+            match override_ {
+                Some(path) => {
+                    let path = path
+                        .parse::<TokenStream>()
+                        .map_err(|e| eyre::eyre!("invalid override type {path}: {e}"))?;
+                    // serde types are (de)serialized by the wrapper
+                    Ok(if is_json {
+                        FieldType {
+                            ty: quote! { #json<#path> },
+                            json: true,
+                            borrowed: false,
+                        }
+                    } else {
+                        FieldType {
+                            ty: path,
+                            json: false,
+                            borrowed: false,
+                        }
+                    })
+                }
+                None if borrowed => Ok(match quote_borrowed_type(ty, &oid)? {
+                    Some(ty) => FieldType {
+                        ty,
+                        json: false,
+                        borrowed: true,
+                    },
+                    None => FieldType {
+                        ty: quote_type(ty, &oid)?,
+                        json: false,
+                        borrowed: false,
+                    },
+                }),
+                None => Ok(FieldType {
+                    ty: quote_type(ty, &oid)?,
+                    json: false,
+                    borrowed: false,
+                }),
+            }
+        };
+
+    // `-- @derive Hash, Eq` adds derives to the structs of a single statement, `-PartialEq`
+    // drops a configured one
+    let annotated_derives = ps
+        .annotations
+        .get_all("derive")
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .collect::<Vec<_>>();
+    let dropped_derives = annotated_derives
+        .iter()
+        .filter_map(|d| d.strip_prefix('-'))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    let derives = config
+        .derives
+        .iter()
+        .map(String::as_str)
+        .chain(
+            annotated_derives
+                .iter()
+                .copied()
+                .filter(|d| !d.starts_with('-')),
+        )
+        .chain(config.serde.serialize.then_some("serde::Serialize"))
+        .chain(config.serde.deserialize.then_some("serde::Deserialize"))
+        .collect::<Vec<_>>();
+    if let Some(d) = dropped_derives.iter().find(|d| !derives.contains(d)) {
+        eyre::bail!("@derive -{d} drops a derive that isn't generated");
+    }
+    let derives = derives
+        .into_iter()
+        .filter(|d| !dropped_derives.contains(d))
+        .collect::<Vec<_>>();
+    let serde = derives.iter().any(|d| d.starts_with("serde::"));
+    let quote_derives = |derives: &[&str]| -> eyre::Result<TokenStream> {
+        if derives.is_empty() {
+            return Ok(quote! {});
+        }
+        let serde_attr = match config.serde.rename_all {
+            Some(rule) if derives.iter().any(|d| d.starts_with("serde::")) => {
+                let rule = rule.as_str();
+                quote! { #[serde(rename_all = #rule)] }
+            }
+            _ => quote! {},
+        };
+        let derives = derives
+            .iter()
+            .map(|d| {
+                d.parse::<TokenStream>()
+                    .map_err(|e| eyre::eyre!("invalid derive {d}: {e}"))
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        Ok(quote! {
+            #[derive(#(#derives),*)]
+            #serde_attr
+        })
+    };
+    let derive_attr = quote_derives(&derives)?;
+
+    let json_serde_attr = |field: &FieldType, nullable: bool, serde: bool| match (serde, field.json)
+    {
+        (true, true) if nullable => quote! { #[serde(with = "json_serde::option")] },
+        (true, true) => quote! { #[serde(with = "json_serde")] },
+        _ => quote! {},
+    };
+
+    check_unique_fields(ps.parameter_types.iter().map(|p| p.name.as_str()))?;
+    check_unique_fields(ps.result_types.iter().map(|c| c.name.as_str()))?;
+
     let pascal_name = ps.name.to_case(Case::Pascal);
-    let rows_struct_ident = format_ident!("{}Rows", pascal_name);
-    let params_struct_ident = format_ident!("{}Params", pascal_name);
+    let rows_struct_ident = format_ident!("{}{}", pascal_name, config.naming.rows_suffix);
+    let params_struct_ident = format_ident!("{}{}", pascal_name, config.naming.params_suffix);
 
-    let fn_name = format_ident!("{}", ps.name);
-    let sql_statement = ps.statement.to_string();
+    let fn_name = field_ident(&ps.name);
+    let (statement, statements_param) = if config.output.statements {
+        (quote! { &s.#fn_name }, quote! { s: &Statements, })
+    } else {
+        let sql_statement = ps.statement.to_string();
+        (quote! { #sql_statement }, quote! {})
+    };
 
     let has_params = !ps.parameter_types.is_empty();
-    let param_params = if has_params {
+    let borrowed = config.params.mode == ParamsMode::Borrowed;
+    let param_field_types = ps
+        .parameter_types
+        .iter()
+        .map(|p| quote_field_type(&p.type_, p.source.as_ref(), &p.name, borrowed))
+        .collect::<eyre::Result<Vec<_>>>()?;
+    // only structs with a borrowed field are generic over the lifetime
+    let has_lifetime = param_field_types.iter().any(|t| t.borrowed);
+    let (params_generics, params_args, params_derives) = if has_lifetime {
+        // `&'a str` and `&'a [..]` can't be deserialized from owned data
+        let derives = derives
+            .iter()
+            .copied()
+            .filter(|d| *d != "serde::Deserialize")
+            .collect::<Vec<_>>();
+        (quote! { <'a> }, quote! { <'_> }, derives)
+    } else {
+        (quote! {}, quote! {}, derives.clone())
+    };
+    let params_serde = params_derives.iter().any(|d| d.starts_with("serde::"));
+    let param_types = ps
+        .parameter_types
+        .iter()
+        .zip(param_field_types)
+        .map(|(p, field_type)| {
+            let field_ident = field_ident(&p.name);
+            let serde_attr = json_serde_attr(&field_type, p.is_nullable, params_serde);
+            helpers.json_serde |= !serde_attr.is_empty();
+            let field_type = field_type.ty;
+
+            if p.is_nullable {
+                quote! {
+                    #serde_attr
+                    pub #field_ident: Option<#field_type>
+                }
+            } else {
+                quote! {
+                    #serde_attr
+                    pub #field_ident: #field_type
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    let params_derive_attr = quote_derives(&params_derives)?;
+
+    let param_params = if ps.bulk {
+        quote! {
+            ps: Vec<#params_struct_ident #params_args>
+        }
+    } else if has_params {
         quote! {
-            p: #params_struct_ident
+            p: #params_struct_ident #params_args
         }
     } else {
         quote! {}
     };
     let params_struct = if has_params {
-        let param_types = ps
-            .parameter_types
-            .iter()
-            .map(|p| {
-                let field_type = quote_type(&p.type_)?;
-                let field_ident = format_ident!("{}", p.name);
-
-                Ok(quote! {
-                    pub #field_ident: #field_type
-                })
-            })
-            .collect::<eyre::Result<Vec<_>>>()?;
-
         quote! {
-            pub struct #params_struct_ident{
+            #params_derive_attr
+            pub struct #params_struct_ident #params_generics {
                 #(#param_types,)*
             }
         }
@@ -75,100 +631,977 @@ fn gen_fn(ps: PrepareStatement) -> eyre::Result<String> {
         quote! {}
     };
 
-    let rows_struct = {
-        let result_fields = ps
+    let (result_fields, json_attrs): (Vec<_>, Vec<_>) = ps
+        .result_types
+        .iter()
+        .map(|c| {
+            let field_type = quote_field_type(&c.type_, c.source.as_ref(), &c.name, false)?;
+            let serde_attr = json_serde_attr(&field_type, c.is_nullable, serde);
+            let field_type = field_type.ty;
+            Ok(if c.is_nullable {
+                (quote! { Option<#field_type> }, serde_attr)
+            } else {
+                (field_type, serde_attr)
+            })
+        })
+        .collect::<eyre::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+
+    // a single column is returned as is, without a struct around it
+    let (rows_struct, row_type) = if ps.scalar {
+        (quote! {}, result_fields[0].clone())
+    } else {
+        let field_idents = ps
             .result_types
             .iter()
-            .map(|c| {
-                let field_type = quote_type(&c.type_)?;
-                let field_ident = format_ident!("{}", c.name);
-
-                Ok(if c.is_nullable {
-                    quote! {
-                        pub #field_ident: Option<#field_type>
-                    }
-                } else {
-                    quote! {
-                        pub #field_ident: #field_type
+            .map(|c| field_ident(&c.name))
+            .collect::<Vec<_>>();
+        helpers.json_serde |= json_attrs.iter().any(|a| !a.is_empty());
+        // sqlx reads the rows by column name
+        let (rows_derive_attr, sqlx_attrs) = if config.backend == Backend::Sqlx {
+            if let Some(name) = ps
+                .column_names
+                .iter()
+                .enumerate()
+                .find_map(|(i, n)| ps.column_names[..i].contains(n).then_some(n))
+            {
+                eyre::bail!("sqlx reads rows by column name, alias the duplicated column {name}");
+            }
+            let sqlx_attrs = field_idents
+                .iter()
+                .zip(&ps.column_names)
+                .map(|(ident, name)| {
+                    if ident == name {
+                        quote! {}
+                    } else {
+                        quote! { #[sqlx(rename = #name)] }
                     }
                 })
-            })
-            .collect::<eyre::Result<Vec<_>>>()?;
-        quote! {
-            pub struct #rows_struct_ident{
-                #(#result_fields,)*
-            }
-        }
+                .collect();
+            let mut derives = derives.clone();
+            derives.push("sqlx::FromRow");
+            (quote_derives(&derives)?, sqlx_attrs)
+        } else {
+            (derive_attr, vec![quote! {}; field_idents.len()])
+        };
+        (
+            quote! {
+                #rows_derive_attr
+                pub struct #rows_struct_ident{
+                    #(#json_attrs #sqlx_attrs pub #field_idents: #result_fields,)*
+                }
+            },
+            quote! { #rows_struct_ident },
+        )
     };
 
-    // Generate param binding for the query
-    let param_binding = if has_params {
-        // Create parameter references for binding
-        let param_refs = ps
+    // Generate param binding for the query, `@bulk` binds an array of each field
+    let param_refs = ps
+        .parameter_types
+        .iter()
+        .map(|p| {
+            let field_ident = field_ident(&p.name);
+            if ps.bulk {
+                quote! { &ps.iter().map(|p| &p.#field_ident).collect::<Vec<_>>() }
+            } else {
+                quote! { &p.#field_ident }
+            }
+        })
+        .collect::<Vec<_>>();
+    if ps.bulk && (config.output.query_typed || config.backend == Backend::Sqlx) {
+        eyre::bail!("@bulk can't be used with output.query_typed or the sqlx backend");
+    }
+    let (param_binding, raw_params) = if config.output.query_typed {
+        let types = ps
             .parameter_types
             .iter()
-            .map(|p| {
-                let field_ident = format_ident!("{}", p.name);
-
-                Ok(quote! { &p.#field_ident })
-            })
+            .map(|p| quote_type_const(&p.type_, &pg))
             .collect::<eyre::Result<Vec<_>>>()?;
-
-        quote! { &[#(#param_refs),*] }
+        let params_len = proc_macro2::Literal::usize_unsuffixed(param_refs.len());
+        (
+            quote! { &[#((#param_refs, #types)),*] },
+            quote! {
+                let params: [(
+                    &(dyn #pg::types::ToSql + Sync),
+                    #pg::types::Type,
+                ); #params_len] = [#((#param_refs, #types)),*];
+            },
+        )
     } else {
-        quote! { &[] }
+        let params_len = proc_macro2::Literal::usize_unsuffixed(param_refs.len());
+        (
+            quote! { &[#(#param_refs),*] },
+            quote! {
+                let params: [&(dyn #pg::types::ToSql + Sync); #params_len] =
+                    [#(#param_refs),*];
+            },
+        )
     };
 
-    let get_expressions = {
+    let row_expr = if ps.scalar {
+        quote! { r.get(0) }
+    } else {
         let get_exprs = ps
             .result_types
             .iter()
             .enumerate()
             .map(|(i, c)| {
-                let field_ident = format_ident!("{}", c.name);
+                let field_ident = field_ident(&c.name);
                 let i = proc_macro2::Literal::usize_unsuffixed(i);
 
                 quote! { #field_ident: r.get(#i) }
             })
             .collect::<Vec<_>>();
 
-        quote! { #(#get_exprs),* }
+        quote! {
+            #rows_struct_ident{
+                #(#get_exprs),*
+            }
+        }
+    };
+
+    let stream = config.output.stream || ps.annotations.get_all("stream").next().is_some();
+    let batch = has_params
+        && !ps.bulk
+        && (config.output.batch || ps.annotations.get_all("batch").next().is_some());
+    if batch && config.backend != Backend::TokioPostgres {
+        eyre::bail!("batch functions need the tokio-postgres backend");
+    }
+    let rows_struct = match ps.client_method {
+        ClientMethod::Execute => quote! {},
+        _ => rows_struct,
+    };
+    let return_type = match ps.client_method {
+        ClientMethod::Query => quote! { Vec<#row_type> },
+        ClientMethod::QueryOne => quote! { #row_type },
+        ClientMethod::QueryOpt => quote! { Option<#row_type> },
+        ClientMethod::Execute => quote! { u64 },
+    };
+    // `*_copy` streams the rows of a plain insert with a binary COPY
+    let copy_in = if config.output.copy || ps.annotations.get_all("copy").next().is_some() {
+        if config.backend == Backend::Sqlx {
+            eyre::bail!("@copy can't be used with the sqlx backend");
+        }
+        match &ps.copy_in {
+            Some(copy_in) => {
+                let params = copy_in
+                    .params
+                    .iter()
+                    .map(|&i| &ps.parameter_types[i])
+                    .collect::<Vec<_>>();
+                let types = params
+                    .iter()
+                    .map(|p| quote_type_const(&p.type_, &pg))
+                    .collect::<eyre::Result<Vec<_>>>()?;
+                let refs = params
+                    .iter()
+                    .map(|p| {
+                        let field_ident = field_ident(&p.name);
+                        quote! { &p.#field_ident }
+                    })
+                    .collect();
+                helpers.copy_in_client |= config.backend == Backend::TokioPostgres;
+                Some(CopyFn {
+                    statement: copy_in.statement.clone(),
+                    types,
+                    refs,
+                })
+            }
+            // the whole file opted in, only plain inserts get one
+            None if config.output.copy => None,
+            None => eyre::bail!(
+                "@copy needs an INSERT INTO t (cols) VALUES ($1, ...) writing each parameter once"
+            ),
+        }
+    } else {
+        None
     };
 
-    // Generate the function body with the appropriate try_get expressions
-    let paragraph = match ps.client_method {
-        crate::code_analysis::ClientMethod::Query => {
-            quote! {
-                #params_struct
-                #rows_struct
+    // `*Error` maps the constraints the statement can violate
+    let (error_enum, error_ident) = if config.output.constraint_errors && !ps.violations.is_empty()
+    {
+        let error_ident = format_ident!("{}{}", pascal_name, config.naming.error_suffix);
+        (
+            gen_error_enum(&error_ident, &ps.violations, &pg),
+            Some(error_ident),
+        )
+    } else {
+        (quote! {}, None)
+    };
 
-                pub async fn #fn_name(
-                    c: &impl tokio_postgres::GenericClient,
-                    #param_params
-                ) -> Result<Vec<#rows_struct_ident>, tokio_postgres::Error> {
-                    c.query(#sql_statement, #param_binding).await.map(|rs| {
-                        rs.into_iter()
-                            .map(|r| #rows_struct_ident{
-                                #get_expressions
-                            })
-                            .collect()
-                    })
+    let parts = FnParts {
+        fn_name,
+        statements_param,
+        param_params,
+        params_struct_ident,
+        has_lifetime,
+        statement,
+        param_refs,
+        param_binding,
+        raw_params,
+        return_type,
+        row_type,
+        row_expr,
+        stream: stream && matches!(ps.client_method, ClientMethod::Query),
+        batch,
+        copy_in,
+        error_ident,
+    };
+    let fns = match config.backend {
+        Backend::TokioPostgres => gen_tokio_postgres_fns(config, &ps, &parts),
+        Backend::Postgres => gen_postgres_fns(config, &ps, &parts),
+        Backend::Sqlx => gen_sqlx_fns(config, &ps, &parts),
+    };
+    let paragraph = quote! {
+        #params_struct
+        #rows_struct
+
+        #fns
+        #error_enum
+    };
+
+    Ok(prettyplease::unparse(&syn::parse2(paragraph)?))
+}
+
+/// Async functions of a statement on `tokio_postgres`
+fn gen_tokio_postgres_fns(config: &Config, ps: &PrepareStatement, f: &FnParts) -> TokenStream {
+    let client = Client::new(Backend::TokioPostgres);
+    let main_fn = gen_main_fn(config, ps, f, &client);
+
+    // `*_stream` maps the rows as they arrive instead of collecting them
+    let stream_fn = if f.stream {
+        let FnParts {
+            statements_param,
+            param_params,
+            statement,
+            raw_params,
+            row_type,
+            row_expr,
+            ..
+        } = f;
+        let Client { client_param, .. } = &client;
+        let stream_fn_name = field_ident(&format!("{}_stream", ps.name));
+        let query_raw = query_raw(config);
+        let instrument = gen_instrument(config, ps, true);
+        quote! {
+            #instrument
+            pub async fn #stream_fn_name(
+                #client_param,
+                #statements_param
+                #param_params
+            ) -> Result<
+                impl futures::Stream<Item = Result<#row_type, tokio_postgres::Error>>,
+                tokio_postgres::Error,
+            > {
+                #raw_params
+                c.#query_raw(#statement, params)
+                    .await
+                    .map(|rs| futures::StreamExt::map(rs, |r| r.map(|r| #row_expr)))
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let copy_fn = match &f.copy_in {
+        Some(CopyFn {
+            statement,
+            types,
+            refs,
+        }) => {
+            let copy_fn_name = field_ident(&format!("{}_copy", ps.name));
+            let (params_struct_ident, params_generics) = params_generics(f);
+            let instrument = gen_instrument(config, ps, true);
+            quote! {
+                #instrument
+                pub async fn #copy_fn_name #params_generics(
+                    c: &impl CopyInClient,
+                    ps: impl IntoIterator<Item = #params_struct_ident #params_generics>,
+                ) -> Result<u64, tokio_postgres::Error> {
+                    let writer = c.binary_copy_in(#statement, &[#(#types),*]).await?;
+                    let mut writer = std::pin::pin!(writer);
+                    for p in ps {
+                        writer.as_mut().write(&[#(#refs),*]).await?;
+                    }
+                    writer.finish().await
                 }
             }
         }
-        crate::code_analysis::ClientMethod::Execute => {
+        None => quote! {},
+    };
+    let batch_fn = if f.batch {
+        gen_batch_fn(config, ps, f)
+    } else {
+        quote! {}
+    };
+    let pool_fns = gen_pool_fns(config, ps, f);
+
+    quote! {
+        #main_fn
+        #stream_fn
+        #copy_fn
+        #batch_fn
+        #pool_fns
+    }
+}
+
+/// Blocking functions of a statement on `postgres`
+fn gen_postgres_fns(config: &Config, ps: &PrepareStatement, f: &FnParts) -> TokenStream {
+    let main_fn = gen_main_fn(config, ps, f, &Client::new(Backend::Postgres));
+
+    // `*_iter` maps the rows as they are read instead of collecting them
+    let iter_fn = if f.stream {
+        let FnParts {
+            statements_param,
+            param_params,
+            statement,
+            raw_params,
+            row_type,
+            row_expr,
+            ..
+        } = f;
+        let iter_fn_name = field_ident(&format!("{}_iter", ps.name));
+        let query_raw = query_raw(config);
+        // `err` runs a blocking body in a closure, which the returned iterator can't borrow from
+        let instrument = gen_instrument(config, ps, false);
+        // the rows borrow the client, named since `s` and the params can borrow too
+        quote! {
+            #instrument
+            pub fn #iter_fn_name<'c>(
+                c: &'c mut impl postgres::GenericClient,
+                #statements_param
+                #param_params
+            ) -> Result<
+                impl postgres::fallible_iterator::FallibleIterator<
+                    Item = #row_type,
+                    Error = postgres::Error,
+                > + 'c,
+                postgres::Error,
+            > {
+                #raw_params
+                c.#query_raw(#statement, params).map(|rs| {
+                    postgres::fallible_iterator::FallibleIterator::map(rs, |r| Ok(#row_expr))
+                })
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let copy_fn = match &f.copy_in {
+        Some(CopyFn {
+            statement,
+            types,
+            refs,
+        }) => {
+            let copy_fn_name = field_ident(&format!("{}_copy", ps.name));
+            let (params_struct_ident, params_generics) = params_generics(f);
+            let instrument = gen_instrument(config, ps, true);
             quote! {
-                #params_struct
+                #instrument
+                pub fn #copy_fn_name #params_generics(
+                    c: &mut impl postgres::GenericClient,
+                    ps: impl IntoIterator<Item = #params_struct_ident #params_generics>,
+                ) -> Result<u64, postgres::Error> {
+                    let writer = c.copy_in(#statement)?;
+                    let mut writer =
+                        postgres::binary_copy::BinaryCopyInWriter::new(writer, &[#(#types),*]);
+                    for p in ps {
+                        writer.write(&[#(#refs),*])?;
+                    }
+                    writer.finish()
+                }
+            }
+        }
+        None => quote! {},
+    };
 
-                pub async fn #fn_name(
-                    c: &impl tokio_postgres::GenericClient,
-                    #param_params
-                ) -> Result<u64, tokio_postgres::Error> {
-                    c.execute(#sql_statement, #param_binding).await
+    quote! {
+        #main_fn
+        #iter_fn
+        #copy_fn
+    }
+}
+
+/// Enum of the constraints a statement can violate, matched by SQLSTATE, table and name
+fn gen_error_enum(
+    ident: &proc_macro2::Ident,
+    violations: &[Violation],
+    pg: &TokenStream,
+) -> TokenStream {
+    let mut variants: Vec<proc_macro2::Ident> = Vec::with_capacity(violations.len());
+    for v in violations {
+        let columns = v
+            .columns
+            .iter()
+            .map(|c| {
+                c.strip_suffix("_id")
+                    .filter(|_| v.kind == ConstraintKind::ForeignKey)
+                    .unwrap_or(c)
+            })
+            .collect::<Vec<_>>()
+            .join("_");
+        let target = if columns.is_empty() {
+            &v.constraint
+        } else {
+            &columns
+        };
+        let name = match v.kind {
+            ConstraintKind::PrimaryKey | ConstraintKind::Unique => format!("unique_{target}"),
+            ConstraintKind::ForeignKey if v.referencing => format!("referenced_by_{}", v.table),
+            ConstraintKind::ForeignKey => format!("fk_{target}"),
+            ConstraintKind::Check => format!("check_{target}"),
+            ConstraintKind::Exclusion => format!("exclusion_{target}"),
+        };
+        let mut variant = format_ident!("{}", name.to_case(Case::Pascal));
+        // two constraints on the same columns keep their own name
+        if variants.contains(&variant) {
+            variant = format_ident!(
+                "{}",
+                format!("{name}_{}", v.constraint).to_case(Case::Pascal)
+            );
+        }
+        variants.push(variant);
+    }
+
+    let docs = violations
+        .iter()
+        .map(|v| format!(" `{}` on `{}` is violated", v.constraint, v.table));
+    let messages = violations
+        .iter()
+        .map(|v| format!("violates constraint {} on {}", v.constraint, v.table));
+    let arms = violations.iter().zip(&variants).map(|(v, variant)| {
+        let code = format_ident!(
+            "{}",
+            match v.kind {
+                ConstraintKind::PrimaryKey | ConstraintKind::Unique => "UNIQUE_VIOLATION",
+                ConstraintKind::ForeignKey => "FOREIGN_KEY_VIOLATION",
+                ConstraintKind::Check => "CHECK_VIOLATION",
+                ConstraintKind::Exclusion => "EXCLUSION_VIOLATION",
+            }
+        );
+        let (table, constraint) = (&v.table, &v.constraint);
+        quote! {
+            (#table, #constraint) if *code == #pg::error::SqlState::#code => Some(Self::#variant)
+        }
+    });
+    quote! {
+        #[derive(Debug)]
+        pub enum #ident {
+            #(#[doc = #docs] #variants,)*
+            Db(#pg::Error),
+        }
+        impl std::fmt::Display for #ident {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    #(Self::#variants => f.write_str(#messages),)*
+                    Self::Db(e) => e.fmt(f),
                 }
             }
         }
+        impl std::error::Error for #ident {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                match self {
+                    Self::Db(e) => Some(e),
+                    _ => None,
+                }
+            }
+        }
+        impl From<#pg::Error> for #ident {
+            fn from(e: #pg::Error) -> Self {
+                let violation = e.as_db_error().and_then(|db| {
+                    let code = db.code();
+                    match (db.table()?, db.constraint()?) {
+                        #(#arms,)*
+                        _ => None,
+                    }
+                });
+                violation.unwrap_or(Self::Db(e))
+            }
+        }
+    }
+}
+
+/// sqlx binds the parameters on the query builder, and maps rows with `FromRow`
+fn gen_sqlx_fns(config: &Config, ps: &PrepareStatement, f: &FnParts) -> TokenStream {
+    let FnParts {
+        fn_name,
+        param_params,
+        params_struct_ident,
+        has_lifetime,
+        statement,
+        return_type,
+        row_type,
+        stream,
+        ..
+    } = f;
+    let binds = ps
+        .parameter_types
+        .iter()
+        .map(|p| {
+            let field_ident = field_ident(&p.name);
+            quote! { .bind(p.#field_ident) }
+        })
+        .collect::<Vec<_>>();
+    let query = if ps.scalar {
+        quote! { sqlx::query_scalar::<_, #row_type>(#statement) #(#binds)* }
+    } else {
+        quote! { sqlx::query_as::<_, #row_type>(#statement) #(#binds)* }
     };
 
-    Ok(prettyplease::unparse(&syn::parse2(paragraph)?))
+    let body = match ps.client_method {
+        ClientMethod::Query => quote! { #query.fetch_all(c).await },
+        ClientMethod::QueryOne => quote! { #query.fetch_one(c).await },
+        ClientMethod::QueryOpt => quote! { #query.fetch_optional(c).await },
+        ClientMethod::Execute => quote! {
+            sqlx::query(#statement) #(#binds)*
+                .execute(c)
+                .await
+                .map(|r| r.rows_affected())
+        },
+    };
+
+    // the stream borrows the executor and the params for as long as it runs
+    let stream_fn = if *stream {
+        let stream_fn_name = field_ident(&format!("{}_stream", ps.name));
+        let stream_params = match (ps.parameter_types.is_empty(), has_lifetime) {
+            (true, _) => quote! {},
+            (false, false) => quote! { p: #params_struct_ident },
+            (false, true) => quote! { p: #params_struct_ident<'e> },
+        };
+        quote! {
+            pub fn #stream_fn_name<'e>(
+                c: impl sqlx::PgExecutor<'e> + 'e,
+                #stream_params
+            ) -> impl futures::Stream<Item = Result<#row_type, sqlx::Error>> + 'e {
+                #query.fetch(c)
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let (instrument, body) = gen_traced(config, ps, body, return_type, &quote! { sqlx::Error });
+
+    quote! {
+        #instrument
+        pub async fn #fn_name(
+            c: impl sqlx::PgExecutor<'_>,
+            #param_params
+        ) -> Result<#return_type, sqlx::Error> {
+            #body
+        }
+        #stream_fn
+    }
+}
+
+/// Function running the statement with the client method matching its rows
+fn gen_main_fn(
+    config: &Config,
+    ps: &PrepareStatement,
+    f: &FnParts,
+    client: &Client,
+) -> TokenStream {
+    let FnParts {
+        fn_name,
+        statements_param,
+        param_params,
+        statement,
+        param_binding,
+        return_type,
+        error_ident,
+        ..
+    } = f;
+    let Client {
+        pg,
+        client_param,
+        asyncness,
+        ..
+    } = client;
+    let body = gen_query_body(
+        ps,
+        f,
+        client,
+        config.output.query_typed,
+        statement,
+        param_binding,
+    );
+    let (error, body) = match error_ident {
+        Some(error_ident) => (
+            quote! { #error_ident },
+            quote! { #body.map_err(#error_ident::from) },
+        ),
+        None => (quote! { #pg::Error }, body),
+    };
+    let (instrument, body) = gen_traced(config, ps, body, return_type, &error);
+
+    quote! {
+        #instrument
+        pub #asyncness fn #fn_name(
+            #client_param,
+            #statements_param
+            #param_params
+        ) -> Result<#return_type, #error> {
+            #body
+        }
+    }
+}
+
+/// Runs `statement` with the client method matching the rows and maps them
+fn gen_query_body(
+    ps: &PrepareStatement,
+    f: &FnParts,
+    client: &Client,
+    typed: bool,
+    statement: &TokenStream,
+    param_binding: &TokenStream,
+) -> TokenStream {
+    let FnParts { row_expr, .. } = f;
+    let Client { await_, .. } = client;
+
+    // the typed methods send the parameter types along instead of preparing the statement
+    let (query, query_one, query_opt, execute) = if typed {
+        (
+            quote! { query_typed },
+            quote! { query_typed_one },
+            quote! { query_typed_opt },
+            quote! { execute_typed },
+        )
+    } else {
+        (
+            quote! { query },
+            quote! { query_one },
+            quote! { query_opt },
+            quote! { execute },
+        )
+    };
+
+    match ps.client_method {
+        ClientMethod::Query => quote! {
+            c.#query(#statement, #param_binding)#await_.map(|rs| {
+                rs.into_iter()
+                    .map(|r| #row_expr)
+                    .collect()
+            })
+        },
+        ClientMethod::QueryOne => {
+            quote! { c.#query_one(#statement, #param_binding)#await_.map(|r| #row_expr) }
+        }
+        ClientMethod::QueryOpt => quote! {
+            c.#query_opt(#statement, #param_binding)
+                #await_
+                .map(|r| r.map(|r| #row_expr))
+        },
+        ClientMethod::Execute => quote! { c.#execute(#statement, #param_binding)#await_ },
+    }
+}
+
+/// `*_batch` prepares once and polls every execution concurrently, which tokio-postgres
+/// pipelines on the connection
+fn gen_batch_fn(config: &Config, ps: &PrepareStatement, f: &FnParts) -> TokenStream {
+    let FnParts {
+        fn_name,
+        statements_param,
+        params_struct_ident,
+        has_lifetime,
+        param_refs,
+        return_type,
+        ..
+    } = f;
+    let batch_fn_name = field_ident(&format!("{}_batch", ps.name));
+    let params_args = if *has_lifetime {
+        quote! { <'_> }
+    } else {
+        quote! {}
+    };
+    let (prepare, statement) = if config.output.statements {
+        (quote! {}, quote! { &s.#fn_name })
+    } else {
+        let sql_statement = ps.statement.to_string();
+        (
+            quote! { let stmt = c.prepare(#sql_statement).await?; },
+            quote! { &stmt },
+        )
+    };
+    let body = gen_query_body(
+        ps,
+        f,
+        &Client::new(Backend::TokioPostgres),
+        false,
+        &statement,
+        &quote! { &[#(#param_refs),*] },
+    );
+    let instrument = gen_instrument(config, ps, true);
+    quote! {
+        #instrument
+        pub async fn #batch_fn_name(
+            c: &impl tokio_postgres::GenericClient,
+            #statements_param
+            ps: impl IntoIterator<Item = #params_struct_ident #params_args>,
+        ) -> Result<Vec<Result<#return_type, tokio_postgres::Error>>, tokio_postgres::Error> {
+            #prepare
+            let ps = ps.into_iter().collect::<Vec<_>>();
+            Ok(futures::future::join_all(ps.iter().map(|p| async { #body })).await)
+        }
+    }
+}
+
+/// `*_cached` and `*_pooled` taking their connection from a pool
+fn gen_pool_fns(config: &Config, ps: &PrepareStatement, f: &FnParts) -> TokenStream {
+    let FnParts {
+        fn_name,
+        param_params,
+        param_refs,
+        return_type,
+        ..
+    } = f;
+
+    // `*_cached` prepares through the deadpool statement cache, reused per connection
+    let deadpool_fn = if config.output.deadpool {
+        let cached_fn_name = field_ident(&format!("{}_cached", ps.name));
+        let sql_statement = ps.statement.to_string();
+        let body = gen_query_body(
+            ps,
+            f,
+            &Client::new(Backend::TokioPostgres),
+            false,
+            &quote! { &stmt },
+            &quote! { &[#(#param_refs),*] },
+        );
+        let instrument = gen_instrument(config, ps, true);
+        quote! {
+            #instrument
+            pub async fn #cached_fn_name(
+                c: &impl deadpool_postgres::GenericClient,
+                #param_params
+            ) -> Result<#return_type, tokio_postgres::Error> {
+                let stmt = c.prepare_cached(#sql_statement).await?;
+                #body
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // `*_pooled` runs on a connection taken from a bb8 pool of `tokio_postgres::Client`, in the
+    // span of the main function
+    let bb8_fn = if config.output.bb8 {
+        let pooled_fn_name = field_ident(&format!("{}_pooled", ps.name));
+        let p_arg = if ps.bulk {
+            quote! { ps }
+        } else if ps.parameter_types.is_empty() {
+            quote! {}
+        } else {
+            quote! { p }
+        };
+        quote! {
+            pub async fn #pooled_fn_name<M>(
+                pool: &bb8::Pool<M>,
+                #param_params
+            ) -> Result<#return_type, bb8::RunError<tokio_postgres::Error>>
+            where
+                M: bb8::ManageConnection<Connection = tokio_postgres::Client, Error = tokio_postgres::Error>,
+            {
+                let c = pool.get().await?;
+                #fn_name(&*c, #p_arg).await.map_err(bb8::RunError::User)
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    quote! {
+        #deadpool_fn
+        #bb8_fn
+    }
+}
+
+/// `#[tracing::instrument]` of the functions besides the main one, `err` records the returned
+/// error
+fn gen_instrument(config: &Config, ps: &PrepareStatement, err: bool) -> TokenStream {
+    if config.output.tracing {
+        instrument_attr(ps, false, err)
+    } else {
+        quote! {}
+    }
+}
+
+/// Span of the main function, whose `body` also records the rows returned or affected
+fn gen_traced(
+    config: &Config,
+    ps: &PrepareStatement,
+    body: TokenStream,
+    return_type: &TokenStream,
+    error: &TokenStream,
+) -> (TokenStream, TokenStream) {
+    if !config.output.tracing {
+        return (quote! {}, body);
+    }
+    let record = match ps.client_method {
+        ClientMethod::Query => quote! {
+            if let Ok(rows) = &result {
+                tracing::Span::current().record("db.response.returned_rows", rows.len());
+            }
+        },
+        ClientMethod::QueryOne => quote! {
+            if result.is_ok() {
+                tracing::Span::current().record("db.response.returned_rows", 1);
+            }
+        },
+        ClientMethod::QueryOpt => quote! {
+            if let Ok(row) = &result {
+                tracing::Span::current()
+                    .record("db.response.returned_rows", usize::from(row.is_some()));
+            }
+        },
+        ClientMethod::Execute => quote! {
+            if let Ok(rows) = &result {
+                tracing::Span::current().record("db.response.affected_rows", rows);
+            }
+        },
+    };
+    (
+        instrument_attr(ps, true, true),
+        quote! {
+            let result: Result<#return_type, #error> = { #body };
+            #record
+            result
+        },
+    )
+}
+
+/// `#[tracing::instrument]` with the OpenTelemetry database fields, `rows` declares the field
+/// filled by `gen_traced` and `err` records the returned error
+fn instrument_attr(ps: &PrepareStatement, rows: bool, err: bool) -> TokenStream {
+    use sqlparser::ast::Statement;
+    let operation = match &*ps.statement {
+        Statement::Insert(_) => "INSERT",
+        Statement::Update { .. } => "UPDATE",
+        Statement::Delete(_) => "DELETE",
+        _ => "SELECT",
+    };
+    let name = &ps.name;
+    let sql = ps.statement.to_string();
+    let rows_field = match (rows, &ps.client_method) {
+        (false, _) => quote! {},
+        (true, ClientMethod::Execute) => {
+            quote! { db.response.affected_rows = tracing::field::Empty, }
+        }
+        (true, _) => quote! { db.response.returned_rows = tracing::field::Empty, },
+    };
+    let err = if err {
+        quote! { err, }
+    } else {
+        quote! {}
+    };
+    quote! {
+        #[tracing::instrument(
+            skip_all,
+            #err
+            fields(
+                db.system = "postgresql",
+                db.operation = #operation,
+                db.prepared_statement = #name,
+                db.statement = #sql,
+                #rows_field
+            )
+        )]
+    }
+}
+
+/// Params struct and its lifetime, named since `'_` isn't allowed in the `impl Trait` argument
+/// of `*_copy`
+fn params_generics(f: &FnParts) -> (&proc_macro2::Ident, TokenStream) {
+    let generics = if f.has_lifetime {
+        quote! { <'a> }
+    } else {
+        quote! {}
+    };
+    (&f.params_struct_ident, generics)
+}
+
+/// `query_raw` of the rows read one by one, `query_typed_raw` with the parameter types
+fn query_raw(config: &Config) -> TokenStream {
+    if config.output.query_typed {
+        quote! { query_typed_raw }
+    } else {
+        quote! { query_raw }
+    }
+}
+
+/// `tokio_postgres::types::Type` constant of a builtin type, e.g. `Type::INT4_ARRAY` for `_int4`
+fn quote_type_const(
+    ty: &tokio_postgres::types::Type,
+    pg: &TokenStream,
+) -> eyre::Result<TokenStream> {
+    if tokio_postgres::types::Type::from_oid(ty.oid()).is_none() {
+        eyre::bail!(
+            "type {ty} isn't builtin, so it has no `Type` constant for query_typed or COPY"
+        );
+    }
+    let name = match ty.name().strip_prefix('_') {
+        Some(member) => format!("{}_ARRAY", member.to_uppercase()),
+        None => ty.name().to_uppercase(),
+    };
+    let ident = format_ident!("{}", name);
+    Ok(quote! { #pg::types::Type::#ident })
+}
+
+/// Rust keywords that can't be used as identifiers without the `r#` prefix
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Turns a sql name (quoted identifiers, literal columns like `_'hello'`) into a snake_case
+/// rust identifier, keywords become raw identifiers
+fn field_ident(name: &str) -> proc_macro2::Ident {
+    let mut snake = String::with_capacity(name.len());
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            if c.is_ascii_uppercase() && prev_lower {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+            prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        } else if !snake.ends_with('_') {
+            snake.push('_');
+            prev_lower = false;
+        }
+    }
+    let snake = snake.trim_matches('_');
+
+    if snake.is_empty() {
+        return format_ident!("_unnamed");
+    }
+    if name.starts_with('_') || snake.starts_with(|c: char| c.is_ascii_digit()) {
+        return format_ident!("_{}", snake);
+    }
+    match snake {
+        // can't be raw identifiers
+        "self" | "super" | "crate" => format_ident!("{}_", snake),
+        _ if KEYWORDS.contains(&snake) => {
+            proc_macro2::Ident::new_raw(snake, proc_macro2::Span::call_site())
+        }
+        _ => format_ident!("{}", snake),
+    }
+}
+
+/// Distinct sql names can end up as the same rust field, e.g. `"Title"` and `title`
+fn check_unique_fields<'a>(names: impl Iterator<Item = &'a str>) -> eyre::Result<()> {
+    let mut seen = std::collections::HashMap::new();
+    for name in names {
+        if let Some(other) = seen.insert(field_ident(name).to_string(), name) {
+            eyre::bail!(
+                "{other} and {name} are both generated as the field {}",
+                field_ident(name)
+            );
+        }
+    }
+    Ok(())
 }
@@ -2,12 +2,15 @@ use std::collections::HashMap;
 
 use eyre::eyre;
 use sqlparser::ast::{
-    CharacterLength, Expr, FromTable, JoinOperator, SelectItem, Statement, TableObject,
-    TimezoneInfo,
+    BinaryOperator, CharacterLength, Expr, FromTable, JoinOperator, SelectItem, Statement,
+    TableObject, TimezoneInfo,
 };
 use tokio_postgres::types::Type;
 
-use crate::{code_analysis::ColumnData, schema::Schema};
+use crate::{
+    code_analysis::{ColumnData, ColumnRef},
+    schema::Schema,
+};
 
 pub(crate) fn infer_output(stmt: &Statement, schema: &Schema) -> eyre::Result<Vec<ColumnData>> {
     match stmt {
@@ -51,12 +54,10 @@ pub(crate) fn infer_output(stmt: &Statement, schema: &Schema) -> eyre::Result<Ve
         Statement::Update {
             returning, table, ..
         } => match &returning {
-            Some(rs) => {
-                let tables = &[table.clone()];
-                rs.iter()
-                    .map(|r| resolve_select_item(r, schema, tables))
-                    .collect()
-            }
+            Some(rs) => rs
+                .iter()
+                .map(|r| resolve_select_item(r, schema, std::slice::from_ref(table)))
+                .collect(),
             None => Ok(vec![]),
         },
         Statement::Query(q) => match &*q.body {
@@ -100,9 +101,9 @@ fn resolve_select_item(
         .collect::<HashMap<_, _>>();
 
     match si {
-        SelectItem::UnnamedExpr(expr) => resolve_expr(&schema, &tables, &columns, expr),
+        SelectItem::UnnamedExpr(expr) => resolve_expr(schema, &tables, &columns, expr),
         SelectItem::ExprWithAlias { expr, alias } => {
-            resolve_expr(&schema, &tables, &columns, expr).map(|c| c.with_name(alias.value.clone()))
+            resolve_expr(schema, &tables, &columns, expr).map(|c| c.with_name(alias.value.clone()))
         }
         e => eyre::bail!("unsupported {e}"),
     }
@@ -116,15 +117,20 @@ fn resolve_expr(
 ) -> Result<ColumnData, eyre::Error> {
     match expr {
         Expr::Identifier(id) => {
-            let (_table, column) = columns.get(id.value.as_str()).expect(&id.value);
+            let (table, column) = columns.get(id.value.as_str()).expect(&id.value);
             Ok(ColumnData {
                 name: column.name.clone(),
                 type_: Type::from_oid(column.type_oid).unwrap(),
                 is_nullable: column.nullable,
+                source: Some(ColumnRef {
+                    table: table.name.clone(),
+                    column: column.name.clone(),
+                }),
+                qualifier: None,
             })
         }
         Expr::CompoundIdentifier(ids) => {
-            let &[ref table_id, ref column_id] = ids.as_slice() else {
+            let [table_id, column_id] = ids.as_slice() else {
                 eyre::bail!("unsupported more then 2 ids");
             };
             let table = tables.get(table_id.value.as_str()).expect(&table_id.value);
@@ -135,6 +141,11 @@ fn resolve_expr(
                 name: column.name.clone(),
                 type_: Type::from_oid(column.type_oid).unwrap(),
                 is_nullable: column.nullable,
+                source: Some(ColumnRef {
+                    table: table.name.clone(),
+                    column: column.name.clone(),
+                }),
+                qualifier: Some(table_id.value.clone()),
             })
         }
         Expr::Cast {
@@ -143,7 +154,7 @@ fn resolve_expr(
             data_type,
             format: _,
         } => {
-            resolve_expr(&schema, tables, columns, expr).map(|c| c.with_type(to_pg_type(data_type)))
+            resolve_expr(schema, tables, columns, expr).map(|c| c.with_type(to_pg_type(data_type)))
         }
         Expr::Value(v) => {
             let (type_, is_nullable) = match &v.value {
@@ -165,7 +176,8 @@ fn resolve_expr(
                 | sqlparser::ast::Value::DoubleQuotedString(_) => (Type::TEXT, false),
                 sqlparser::ast::Value::Boolean(_) => (Type::BOOL, false),
                 sqlparser::ast::Value::Null => (Type::TEXT, true), // TODO: This should be a never type
-                sqlparser::ast::Value::Placeholder(_) => todo!("place_holder_prepare"),
+                // postgres resolves an untyped parameter as text, params are not null by default
+                sqlparser::ast::Value::Placeholder(_) => (Type::TEXT, false),
                 _ => {
                     unreachable!("not supported on postgres")
                 }
@@ -174,6 +186,8 @@ fn resolve_expr(
                 type_,
                 name: format!("_{}", v.value),
                 is_nullable,
+                source: None,
+                qualifier: None,
             })
         }
         Expr::Function(f) => {
@@ -186,33 +200,112 @@ fn resolve_expr(
                 sqlparser::ast::FunctionArguments::Subquery(_query) => true, // TODO: analyze subquery
                 sqlparser::ast::FunctionArguments::List(al) => match al.args.as_slice() {
                     &[] => false,
-                    &[ref arg] => match arg {
+                    [arg] => match arg {
                         sqlparser::ast::FunctionArg::Unnamed(arg) => match arg {
                             sqlparser::ast::FunctionArgExpr::Expr(expr) => {
                                 resolve_expr(schema, tables, columns, expr)?.is_nullable
                             }
-                            sqlparser::ast::FunctionArgExpr::QualifiedWildcard(_) => {
-                                todo!("wtf how should I handle that?")
-                            }
-                            sqlparser::ast::FunctionArgExpr::Wildcard => {
-                                todo!("wtf how should I handle this?")
-                            }
+                            // count(*) and count(t.*)
+                            sqlparser::ast::FunctionArgExpr::QualifiedWildcard(_)
+                            | sqlparser::ast::FunctionArgExpr::Wildcard => false,
                         },
                         e => eyre::bail!("unsupported {e}"),
                     },
                     _ => false, // TODO: wtf how should I guess that?
                 },
-            };
+            } || is_null_on_empty_input(&func.name);
             Ok(ColumnData {
                 type_: Type::from_oid(func.return_type).expect("type not found"),
                 name: func.name.clone(),
                 is_nullable,
+                source: None,
+                qualifier: None,
             })
         }
+        Expr::BinaryOp {
+            left,
+            op: op @ (BinaryOperator::Arrow | BinaryOperator::HashArrow),
+            right,
+        } => {
+            let left = resolve_expr(schema, tables, columns, left)?;
+            if !matches!(left.type_, Type::JSON | Type::JSONB) {
+                eyre::bail!("{op} on non json type {}", left.type_);
+            }
+            Ok(ColumnData {
+                name: json_path_name(&left.name, right),
+                type_: left.type_,
+                is_nullable: true,
+                source: None,
+                qualifier: None,
+            })
+        }
+        Expr::BinaryOp {
+            left,
+            op: op @ (BinaryOperator::LongArrow | BinaryOperator::HashLongArrow),
+            right,
+        } => {
+            let left = resolve_expr(schema, tables, columns, left)?;
+            if !matches!(left.type_, Type::JSON | Type::JSONB) {
+                eyre::bail!("{op} on non json type {}", left.type_);
+            }
+            Ok(ColumnData {
+                name: json_path_name(&left.name, right),
+                type_: Type::TEXT,
+                is_nullable: true,
+                source: None,
+                qualifier: None,
+            })
+        }
+        Expr::Nested(expr) => resolve_expr(schema, tables, columns, expr),
+        Expr::Exists { .. } => Ok(ColumnData {
+            name: "exists".to_owned(),
+            type_: Type::BOOL,
+            is_nullable: false,
+            source: None,
+            qualifier: None,
+        }),
         e => eyre::bail!("unsupported {e}"),
     }
 }
 
+/// Aggregations that return null when there is no row to aggregate
+fn is_null_on_empty_input(func_name: &str) -> bool {
+    matches!(
+        func_name,
+        "sum"
+            | "avg"
+            | "min"
+            | "max"
+            | "bool_and"
+            | "bool_or"
+            | "every"
+            | "array_agg"
+            | "string_agg"
+            | "json_agg"
+            | "jsonb_agg"
+            | "json_object_agg"
+            | "jsonb_object_agg"
+    )
+}
+
+/// Names a json access after the accessed keys, e.g. `profile->'address'->>'city'`
+/// becomes `profile_address_city`
+fn json_path_name(base: &str, path: &Expr) -> String {
+    let Expr::Value(v) = path else {
+        return base.to_owned();
+    };
+    let key = match &v.value {
+        sqlparser::ast::Value::SingleQuotedString(s) => s.clone(),
+        sqlparser::ast::Value::Number(n, _) => n.clone(),
+        _ => return base.to_owned(),
+    };
+    std::iter::once(base)
+        .chain(key.split(|c: char| !c.is_alphanumeric()))
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
 fn to_pg_type(data_type: &sqlparser::ast::DataType) -> Type {
     use sqlparser::ast::DataType::*;
     match data_type {
@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+pub const CONFIG_FILE_NAME: &str = "sqlc.toml";
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Client crate the generated code runs on
+    pub backend: Backend,
+    /// Rust types to use instead of the default mapping
+    pub overrides: Overrides,
+    /// Derives added to every generated struct
+    pub derives: Vec<String>,
+    pub serde: Serde,
+    pub params: Params,
+    pub naming: Naming,
+    pub output: Output,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            overrides: Overrides::default(),
+            derives: vec![
+                "Debug".to_owned(),
+                "Clone".to_owned(),
+                "PartialEq".to_owned(),
+            ],
+            serde: Serde::default(),
+            params: Params::default(),
+            naming: Naming::default(),
+            output: Output::default(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// Async functions over `tokio_postgres::GenericClient`
+    #[default]
+    TokioPostgres,
+    /// Blocking functions over `postgres::GenericClient`
+    Postgres,
+    /// Async functions over `sqlx::PgExecutor`
+    Sqlx,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overrides {
+    /// Postgres type name (e.g. `uuid`, `jsonb`) to rust type path
+    pub types: HashMap<String, String>,
+    /// `table.column` to rust type path, takes precedence over `types`
+    pub columns: HashMap<String, String>,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Serde {
+    /// Derive `serde::Serialize` on the generated structs
+    pub serialize: bool,
+    /// Derive `serde::Deserialize` on the generated structs
+    pub deserialize: bool,
+    pub rename_all: Option<RenameRule>,
+}
+
+/// Same rules of `#[serde(rename_all = "...")]`
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+pub enum RenameRule {
+    #[serde(rename = "lowercase")]
+    Lower,
+    #[serde(rename = "UPPERCASE")]
+    Upper,
+    #[serde(rename = "PascalCase")]
+    Pascal,
+    #[serde(rename = "camelCase")]
+    Camel,
+    #[serde(rename = "snake_case")]
+    Snake,
+    #[serde(rename = "SCREAMING_SNAKE_CASE")]
+    ScreamingSnake,
+    #[serde(rename = "kebab-case")]
+    Kebab,
+    #[serde(rename = "SCREAMING-KEBAB-CASE")]
+    ScreamingKebab,
+}
+
+impl RenameRule {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            RenameRule::Lower => "lowercase",
+            RenameRule::Upper => "UPPERCASE",
+            RenameRule::Pascal => "PascalCase",
+            RenameRule::Camel => "camelCase",
+            RenameRule::Snake => "snake_case",
+            RenameRule::ScreamingSnake => "SCREAMING_SNAKE_CASE",
+            RenameRule::Kebab => "kebab-case",
+            RenameRule::ScreamingKebab => "SCREAMING-KEBAB-CASE",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Params {
+    pub mode: ParamsMode,
+}
+
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamsMode {
+    /// `String`, `Vec<u8>`, `Vec<T>`
+    #[default]
+    Owned,
+    /// `&'a str`, `&'a [u8]`, `&'a [T]`
+    Borrowed,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Naming {
+    pub params_suffix: String,
+    pub rows_suffix: String,
+    pub error_suffix: String,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self {
+            params_suffix: "Params".to_owned(),
+            rows_suffix: "Rows".to_owned(),
+            error_suffix: "Error".to_owned(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Output {
+    /// Extension of the generated file, written aside the sql file
+    pub extension: String,
+    /// Text prepended to every generated file
+    pub header: Option<String>,
+    /// Return a single row for queries that always return one, and the bare value for queries
+    /// of a single column, without the `@returns` annotation
+    pub infer_returns: bool,
+    /// Also generate `*_stream` functions for queries returning many rows
+    pub stream: bool,
+    /// Generate a `Statements` struct preparing every statement once, taken by the functions
+    pub statements: bool,
+    /// Call `query_typed` with the parameter types, skipping the prepare round-trip
+    pub query_typed: bool,
+    /// Also generate `*_batch` functions pipelining many executions of a statement
+    pub batch: bool,
+    /// Also generate `*_copy` functions writing the rows of plain inserts with a binary COPY
+    pub copy: bool,
+    /// Return an `*Error` enum of the constraints a statement can violate
+    pub constraint_errors: bool,
+    /// Wrap the generated functions in `tracing` spans with the OpenTelemetry database fields
+    pub tracing: bool,
+    /// Also generate `*_cached` functions over `deadpool_postgres::GenericClient`, using its
+    /// statement cache
+    pub deadpool: bool,
+    /// Also generate `*_pooled` functions getting a connection from a `bb8::Pool`
+    pub bb8: bool,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            extension: "rs".to_owned(),
+            header: None,
+            infer_returns: false,
+            stream: false,
+            statements: false,
+            query_typed: false,
+            batch: false,
+            copy: false,
+            constraint_errors: false,
+            tracing: false,
+            deadpool: false,
+            bb8: false,
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn parse(raw: &str) -> eyre::Result<Self> {
+        let config: Self = toml::from_str(raw)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> eyre::Result<()> {
+        if self.output.statements && self.output.query_typed {
+            eyre::bail!(
+                "output.statements can't be used with output.query_typed, which takes the sql string"
+            );
+        }
+        if self.backend == Backend::Sqlx && (self.output.statements || self.output.query_typed) {
+            eyre::bail!(
+                "output.statements and output.query_typed can't be used with the sqlx backend"
+            );
+        }
+        if self.backend == Backend::Sqlx && self.output.copy {
+            eyre::bail!("output.copy can't be used with the sqlx backend");
+        }
+        if self.output.constraint_errors && (self.backend == Backend::Sqlx || self.output.bb8) {
+            eyre::bail!(
+                "output.constraint_errors can't be used with the sqlx backend or output.bb8"
+            );
+        }
+        if self.backend != Backend::TokioPostgres && self.output.batch {
+            eyre::bail!("output.batch needs the tokio-postgres backend");
+        }
+        if self.backend != Backend::TokioPostgres && (self.output.deadpool || self.output.bb8) {
+            eyre::bail!("output.deadpool and output.bb8 need the tokio-postgres backend");
+        }
+        if self.output.bb8 && self.output.statements {
+            eyre::bail!(
+                "output.bb8 can't be used with output.statements, which are prepared on a single connection"
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn load(path: &Path) -> eyre::Result<Self> {
+        let raw = tokio::fs::read_to_string(path).await?;
+        Self::parse(&raw).map_err(|e| e.wrap_err(format!("invalid config {path:?}")))
+    }
+
+    /// Walks up from `start` looking for a `sqlc.toml`
+    pub(crate) async fn discover(start: &Path) -> eyre::Result<Option<PathBuf>> {
+        let mut start = tokio::fs::canonicalize(start).await?;
+        if tokio::fs::metadata(&start).await?.is_file() {
+            start.pop();
+        }
+        for dir in start.ancestors() {
+            let candidate = dir.join(CONFIG_FILE_NAME);
+            if tokio::fs::try_exists(&candidate).await? {
+                return Ok(Some(candidate));
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn column_override(&self, table: &str, column: &str) -> Option<&str> {
+        self.overrides
+            .columns
+            .get(&format!("{table}.{column}"))
+            .map(String::as_str)
+    }
+
+    pub(crate) fn type_override(&self, type_: &tokio_postgres::types::Type) -> Option<&str> {
+        self.overrides.types.get(type_.name()).map(String::as_str)
+    }
+}
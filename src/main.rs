@@ -4,9 +4,11 @@ use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
 };
 
+mod annotation;
 mod code_analysis;
 mod code_gen;
 mod code_inference;
+mod config;
 mod schema;
 #[cfg(test)]
 mod test;
@@ -21,6 +23,11 @@ struct Args {
     /// Can also be pass as env "POSTGRES_URL"
     #[arg(short = 'u', long, value_name = "URL")]
     postgres_url: Option<String>,
+
+    /// Config file path.
+    /// Defaults to the first "sqlc.toml" found walking up from `path`
+    #[arg(short = 'c', long, value_name = "FILE")]
+    config: Option<std::path::PathBuf>,
 }
 
 #[tokio::main]
@@ -31,6 +38,17 @@ async fn main() -> eyre::Result<()> {
         .postgres_url
         .or(std::env::var("POSTGRES_URL").ok())
         .context("Missing postgres_url")?;
+    let config_path = match cli.config {
+        Some(path) => Some(path),
+        None => config::Config::discover(&cli.path).await?,
+    };
+    let config = match config_path {
+        Some(path) => {
+            log::info!("using config {path:?}");
+            config::Config::load(&path).await?
+        }
+        None => config::Config::default(),
+    };
     let (client, connection) = tokio_postgres::connect(&url, tokio_postgres::NoTls).await?;
 
     tokio::spawn(async move {
@@ -50,8 +68,8 @@ async fn main() -> eyre::Result<()> {
             }
             log::info!("translating {path:?}");
             let mut sql = File::open(&path).await?;
-            let mut rs = File::create(path.with_extension("rs")).await?;
-            translate_file(&client, &mut sql, &mut rs).await
+            let mut rs = File::create(path.with_extension(&config.output.extension)).await?;
+            translate_file(&client, &config, &mut sql, &mut rs).await
         });
     futures::future::try_join_all(futs).await?;
 
@@ -60,6 +78,7 @@ async fn main() -> eyre::Result<()> {
 
 async fn translate_file<I, O>(
     client: &impl tokio_postgres::GenericClient,
+    config: &config::Config,
     sql: &mut I,
     rs: &mut O,
 ) -> eyre::Result<()>
@@ -70,7 +89,7 @@ where
     let mut stmts_raw = String::new();
     sql.read_to_string(&mut stmts_raw).await?;
 
-    let code = code_gen::gen_file(client, stmts_raw).await?;
+    let code = code_gen::gen_file(client, config, stmts_raw).await?;
     rs.write_all(code.as_bytes()).await?;
 
     Ok(())
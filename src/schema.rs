@@ -32,11 +32,34 @@ impl Table {
 pub struct Func {
     pub name: String,
     pub return_type: tokio_postgres::types::Oid,
+    /// Returns a row per element, like `generate_series` or `unnest`
+    pub returns_set: bool,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintKind {
+    PrimaryKey,
+    Unique,
+    ForeignKey,
+    Check,
+    Exclusion,
+}
+
+#[derive(Debug, Clone)]
+pub struct Constraint {
+    pub name: String,
+    pub kind: ConstraintKind,
+    pub table_oid: tokio_postgres::types::Oid,
+    /// Table referenced by a foreign key
+    pub referenced_table_oid: Option<tokio_postgres::types::Oid>,
+    pub columns: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct Schema {
     pub tables: Vec<Table>,
     pub funcs: Vec<Func>,
+    pub constraints: Vec<Constraint>,
 }
 impl Schema {
     pub(crate) fn find_column_by_id(
@@ -79,14 +102,26 @@ impl Schema {
                 })
                 .collect(),
             funcs: self.funcs.clone(),
+            constraints: self.constraints.clone(),
         }
     }
 
+    pub(crate) fn find_table_by_oid(&self, oid: tokio_postgres::types::Oid) -> Option<&Table> {
+        self.tables.iter().find(|t| t.oid == oid)
+    }
+
     pub(crate) fn find_func_by_name(&self, func_name: &str) -> Option<&Func> {
         self.funcs
             .iter()
             .find(|f| f.name.to_lowercase() == func_name.to_lowercase())
     }
+
+    /// Any overload of the function returns a set
+    pub(crate) fn is_set_returning_func(&self, func_name: &str) -> bool {
+        self.funcs
+            .iter()
+            .any(|f| f.returns_set && f.name.eq_ignore_ascii_case(func_name))
+    }
 }
 
 pub async fn load_schema(c: &impl tokio_postgres::GenericClient) -> eyre::Result<Schema> {
@@ -124,8 +159,35 @@ pub async fn load_schema(c: &impl tokio_postgres::GenericClient) -> eyre::Result
         .map(|r| Func {
             name: r.function_name,
             return_type: r.return_type,
+            returns_set: r.returns_set,
         })
         .collect();
+    let constraints = query::load_constraints(c)
+        .await?
+        .into_iter()
+        .map(|r| {
+            let kind = match r.kind.as_str() {
+                "p" => ConstraintKind::PrimaryKey,
+                "u" => ConstraintKind::Unique,
+                "f" => ConstraintKind::ForeignKey,
+                "c" => ConstraintKind::Check,
+                "x" => ConstraintKind::Exclusion,
+                kind => eyre::bail!("unexpected constraint type {kind}"),
+            };
+            Ok(Constraint {
+                name: r.name,
+                kind,
+                table_oid: r.table_oid,
+                referenced_table_oid: (r.referenced_table_oid != 0)
+                    .then_some(r.referenced_table_oid),
+                columns: r.columns,
+            })
+        })
+        .collect::<eyre::Result<_>>()?;
 
-    Ok(Schema { tables, funcs })
+    Ok(Schema {
+        tables,
+        funcs,
+        constraints,
+    })
 }
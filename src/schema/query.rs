@@ -33,6 +33,7 @@ pub async fn load_schema(
 pub struct LoadFuncsRows {
     pub function_name: String,
     pub return_type: tokio_postgres::types::Oid,
+    pub returns_set: bool,
 }
 pub async fn load_funcs(
     c: &impl tokio_postgres::GenericClient,
@@ -41,7 +42,8 @@ pub async fn load_funcs(
         "
             SELECT
                 p.proname AS function_name,
-                p.prorettype AS return_type
+                p.prorettype AS return_type,
+                p.proretset AS returns_set
             FROM
                 pg_catalog.pg_proc p
             ",
@@ -53,6 +55,35 @@ pub async fn load_funcs(
             .map(|r| LoadFuncsRows {
                 function_name: r.get(0),
                 return_type: r.get(1),
+                returns_set: r.get(2),
+            })
+            .collect()
+    })
+}
+
+pub struct LoadConstraintsRows {
+    pub name: String,
+    pub kind: String,
+    pub table_oid: tokio_postgres::types::Oid,
+    pub referenced_table_oid: tokio_postgres::types::Oid,
+    pub columns: Vec<String>,
+}
+pub async fn load_constraints(
+    c: &impl tokio_postgres::GenericClient,
+) -> Result<Vec<LoadConstraintsRows>, tokio_postgres::Error> {
+    c.query(
+        "SELECT con.conname AS name, con.contype::text AS kind, con.conrelid AS table_oid, con.confrelid AS referenced_table_oid, ARRAY(SELECT a.attname FROM unnest(con.conkey) WITH ORDINALITY AS k (attnum, ord) JOIN pg_catalog.pg_attribute AS a ON a.attrelid = con.conrelid AND a.attnum = k.attnum ORDER BY k.ord) AS columns FROM pg_catalog.pg_constraint AS con WHERE con.contype IN ('p', 'u', 'f', 'c', 'x') AND con.conrelid <> 0 ORDER BY con.conname",
+        &[],
+    )
+    .await
+    .map(|rs| {
+        rs.into_iter()
+            .map(|r| LoadConstraintsRows {
+                name: r.get(0),
+                kind: r.get(1),
+                table_oid: r.get(2),
+                referenced_table_oid: r.get(3),
+                columns: r.get(4),
             })
             .collect()
     })
@@ -28,6 +28,27 @@ GROUP BY
 PREPARE load_funcs AS
 SELECT
     p.proname AS function_name,
-    p.prorettype AS return_type
+    p.prorettype AS return_type,
+    p.proretset AS returns_set
 FROM
-    pg_proc p
+    pg_proc p;
+
+PREPARE load_constraints AS
+SELECT
+    con.conname AS name,
+    con.contype::text AS kind,
+    con.conrelid AS table_oid,
+    con.confrelid AS referenced_table_oid,
+    ARRAY(
+        SELECT a.attname
+        FROM unnest(con.conkey) WITH ORDINALITY AS k(attnum, ord)
+        JOIN pg_attribute a ON a.attrelid = con.conrelid AND a.attnum = k.attnum
+        ORDER BY k.ord
+    ) AS columns
+FROM
+    pg_constraint con
+WHERE
+    con.contype IN ('p', 'u', 'f', 'c', 'x')
+    AND con.conrelid <> 0
+ORDER BY
+    con.conname;
@@ -6,7 +6,7 @@ use testcontainers_modules::{
 };
 use tokio::{io::AsyncWriteExt, sync::Mutex};
 
-use crate::translate_file;
+use crate::{config::Config, translate_file};
 
 pub(crate) async fn db_transaction() -> (
     Arc<ContainerAsync<Postgres>>,
@@ -63,27 +63,72 @@ CREATE TABLE languages (
     language_id INT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
     name text NOT NULL
 );
+
+CREATE TABLE users (
+    user_id INT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name text NOT NULL,
+    profile jsonb,
+    settings json NOT NULL
+);
+
+CREATE TABLE studios (
+    studio_id INT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    name text NOT NULL UNIQUE CHECK (name <> '')
+);
+
+CREATE TABLE releases (
+    release_id INT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    studio_id INT NOT NULL REFERENCES studios
+);
 ";
 
-async fn e2e(ps: &str) -> String {
+/// A statement per client method, for the options generating other functions around them
+const FILM_STATEMENTS: &str =
+    "PREPARE list_films AS SELECT film_id, title FROM films WHERE language_id = $1;
+-- @returns one scalar
+PREPARE count_films AS SELECT count(*) FROM films;
+-- @returns opt scalar
+PREPARE find_title AS SELECT title FROM films WHERE film_id = $1;
+PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;";
+
+async fn e2e(config: &str, ps: &str) -> String {
     let (_c, t) = db_transaction().await;
     t.batch_execute(SEED_TABLES).await.unwrap();
 
+    let config = Config::parse(config).unwrap();
     let mut sql = std::io::Cursor::new(ps);
     let mut rs = std::io::Cursor::new(Vec::new());
 
-    translate_file(&t, &mut sql, &mut rs).await.unwrap();
+    translate_file(&t, &config, &mut sql, &mut rs)
+        .await
+        .unwrap();
     String::from_utf8(rs.into_inner()).unwrap()
 }
 
+async fn e2e_err(config: &str, ps: &str) -> eyre::Report {
+    let (_c, t) = db_transaction().await;
+    t.batch_execute(SEED_TABLES).await.unwrap();
+
+    let config = Config::parse(config).unwrap();
+    let mut sql = std::io::Cursor::new(ps);
+    let mut rs = std::io::Cursor::new(Vec::new());
+
+    translate_file(&t, &config, &mut sql, &mut rs)
+        .await
+        .unwrap_err()
+}
+
 macro_rules! t {
-    ($fname:ident, $arg:literal) => {
+    ($fname:ident, config = $config:literal, $arg:expr) => {
         #[tokio::test]
         async fn $fname() {
-            let rs = crate::test::e2e($arg).await;
+            let rs = crate::test::e2e($config, $arg).await;
             insta::assert_snapshot!(rs);
         }
     };
+    ($fname:ident, $arg:expr) => {
+        t!($fname, config = "", $arg);
+    };
 }
 
 mod select {
@@ -153,6 +198,24 @@ mod select {
             inner_join_using,
             "PREPARE a AS SELECT title, name FROM films INNER JOIN languages using (language_id);"
         );
+        t!(
+            duplicated_names,
+            "PREPARE a AS SELECT f.language_id, l.language_id, u.name, l.name AS language FROM films AS f JOIN languages AS l ON f.language_id = l.language_id JOIN users AS u ON u.user_id = f.film_id;"
+        );
+        t!(
+            duplicated_names_self_join,
+            "PREPARE a AS SELECT f1.title, f2.title FROM films f1 JOIN films f2 ON f2.original_language_id = f1.language_id;"
+        );
+
+        #[tokio::test]
+        async fn duplicated_alias() {
+            let err = crate::test::e2e_err(
+                "",
+                "PREPARE a AS SELECT f.title AS name, l.name AS name FROM films AS f JOIN languages AS l USING (language_id);",
+            )
+            .await;
+            insta::assert_snapshot!(err, @"result column name appears more than once, qualify it with the table alias or add an alias like `AS other_name`");
+        }
     }
 
     mod cast {
@@ -191,6 +254,443 @@ mod select {
     mod case {}
 }
 
+mod identifiers {
+    t!(
+        keywords,
+        "PREPARE a AS SELECT title AS type, film_id AS match, description AS self FROM films WHERE language_id = :ref;"
+    );
+    t!(
+        quoted,
+        r#"PREPARE a AS SELECT title AS "Film Title", film_id AS "filmId", 'hello', 42 FROM films;"#
+    );
+}
+
+mod returns {
+    t!(
+        annotated_one,
+        "-- @returns one
+        PREPARE a AS INSERT INTO films(title, language_id) VALUES ($1, $2) RETURNING film_id, title;"
+    );
+    t!(
+        annotated_opt,
+        "-- @returns opt
+        PREPARE a AS SELECT title FROM films WHERE film_id = $1;"
+    );
+    t!(
+        annotated_scalar,
+        "-- @returns one scalar
+        PREPARE a AS INSERT INTO films(title, language_id) VALUES ($1, $2) RETURNING film_id;"
+    );
+
+    mod inferred {
+        t!(
+            count,
+            config = "output.infer_returns = true",
+            "PREPARE a AS SELECT count(*) FROM films;"
+        );
+        t!(
+            aggregates,
+            config = "output.infer_returns = true",
+            "PREPARE a AS SELECT count(film_id) AS films, count(description) AS described FROM films WHERE title = $1;"
+        );
+        t!(
+            exists,
+            config = "output.infer_returns = true",
+            "PREPARE a AS SELECT EXISTS(SELECT 1 FROM films WHERE film_id = $1);"
+        );
+        t!(
+            set_returning,
+            config = "output.infer_returns = true",
+            "PREPARE a AS SELECT generate_series(1, $1) AS n;"
+        );
+        t!(
+            single_column,
+            config = "output.infer_returns = true",
+            "PREPARE a AS SELECT title FROM films;"
+        );
+        t!(
+            single_nullable_column,
+            config = "output.infer_returns = true",
+            "PREPARE a AS SELECT description FROM films;"
+        );
+        t!(
+            annotated_struct,
+            config = "output.infer_returns = true",
+            "-- @returns many struct
+            PREPARE a AS SELECT title FROM films;"
+        );
+    }
+}
+
+mod stream {
+    t!(
+        config,
+        config = r#"
+            [output]
+            stream = true
+            infer_returns = true
+        "#,
+        "PREPARE list_films AS SELECT film_id, title FROM films WHERE language_id = $1;
+        PREPARE list_titles AS SELECT title FROM films;
+        PREPARE count_films AS SELECT count(*) FROM films;"
+    );
+    t!(
+        annotated,
+        "-- @stream
+        PREPARE list_films AS SELECT film_id, title FROM films;"
+    );
+}
+
+mod statements {
+    t!(
+        config,
+        config = r#"
+            [output]
+            statements = true
+            stream = true
+        "#,
+        "PREPARE find_film AS SELECT film_id, title FROM films WHERE film_id = $1;
+        PREPARE list_titles AS SELECT title FROM films;
+        PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;"
+    );
+}
+
+mod query_typed {
+    t!(
+        config,
+        config = r#"
+            [output]
+            query_typed = true
+            stream = true
+        "#,
+        "PREPARE list_films AS SELECT film_id, title FROM films WHERE language_id = $1 AND film_id = ANY($2);
+        -- @returns one
+        PREPARE count_films AS SELECT count(*) FROM films;
+        -- @returns opt
+        PREPARE find_title AS SELECT title FROM films WHERE film_id = $1;
+        PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;"
+    );
+
+    /// `@returns one` fails like `query_one` when the row is missing
+    #[tokio::test]
+    async fn one_without_row() {
+        let (_c, t) = crate::test::db_transaction().await;
+        t.batch_execute(crate::test::SEED_TABLES).await.unwrap();
+
+        let err = t
+            .query_typed_one(
+                "SELECT title FROM films WHERE film_id = $1",
+                &[(&1, tokio_postgres::types::Type::INT4)],
+            )
+            .await
+            .unwrap_err();
+        insta::assert_snapshot!(err, @"query returned an unexpected number of rows");
+    }
+
+    #[test]
+    fn with_statements() {
+        let err = crate::config::Config::parse(
+            r#"
+            [output]
+            query_typed = true
+            statements = true
+            "#,
+        )
+        .unwrap_err();
+        insta::assert_snapshot!(err, @"output.statements can't be used with output.query_typed, which takes the sql string");
+    }
+}
+
+mod backend {
+    t!(
+        postgres,
+        config = r#"
+            backend = "postgres"
+
+            [output]
+            stream = true
+            statements = true
+        "#,
+        crate::test::FILM_STATEMENTS
+    );
+    t!(
+        postgres_typed,
+        config = r#"
+            backend = "postgres"
+
+            [output]
+            stream = true
+            query_typed = true
+        "#,
+        "PREPARE list_films AS SELECT film_id, title FROM films WHERE language_id = $1;
+        PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;"
+    );
+    t!(
+        sqlx,
+        config = r#"
+            backend = "sqlx"
+
+            [output]
+            stream = true
+        "#,
+        r#"PREPARE list_films AS SELECT f.film_id, f.title AS "Title", l.name FROM films f JOIN languages l USING (language_id) WHERE f.language_id = $1;
+        -- @returns scalar
+        PREPARE list_titles AS SELECT title FROM films;
+        -- @returns one scalar
+        PREPARE count_films AS SELECT count(*) FROM films;
+        -- @returns opt scalar
+        PREPARE find_title AS SELECT title FROM films WHERE film_id = $1;
+        PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;"#
+    );
+    t!(
+        sqlx_borrowed,
+        config = r#"
+            backend = "sqlx"
+
+            [params]
+            mode = "borrowed"
+
+            [output]
+            stream = true
+        "#,
+        "-- @returns scalar
+        PREPARE list_films AS SELECT film_id FROM films WHERE title = $1;"
+    );
+
+    #[test]
+    fn sqlx_statements() {
+        let err = crate::config::Config::parse(
+            r#"
+            backend = "sqlx"
+
+            [output]
+            statements = true
+            "#,
+        )
+        .unwrap_err();
+        insta::assert_snapshot!(err, @"output.statements and output.query_typed can't be used with the sqlx backend");
+    }
+}
+
+mod batch {
+    t!(
+        config,
+        config = r#"
+            [output]
+            batch = true
+        "#,
+        "PREPARE create_film AS INSERT INTO films(title) VALUES ($1);
+        -- @returns scalar
+        PREPARE find_title AS SELECT title FROM films WHERE film_id = $1;
+        -- @returns one scalar
+        PREPARE count_films AS SELECT count(*) FROM films;"
+    );
+    t!(
+        annotated,
+        config = r#"
+            [output]
+            statements = true
+        "#,
+        "-- @batch
+        PREPARE create_film AS INSERT INTO films(title) VALUES ($1) RETURNING film_id, title;
+        PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;"
+    );
+}
+
+mod copy {
+    t!(
+        config,
+        config = r#"
+            [output]
+            copy = true
+        "#,
+        "PREPARE create_film AS INSERT INTO films(title, language_id) VALUES ($2, $1);
+        PREPARE create_returning AS INSERT INTO films(title) VALUES ($1) RETURNING film_id;
+        PREPARE rename_film AS UPDATE films SET title = $2 WHERE film_id = $1;"
+    );
+    t!(
+        blocking,
+        config = r#"
+            backend = "postgres"
+        "#,
+        "-- @copy
+        PREPARE create_film AS INSERT INTO films(title) VALUES ($1);"
+    );
+    t!(
+        blocking_borrowed,
+        config = r#"
+            backend = "postgres"
+
+            [params]
+            mode = "borrowed"
+        "#,
+        "-- @copy
+        PREPARE create_film AS INSERT INTO films(title, language_id) VALUES ($1, $2);"
+    );
+
+    #[tokio::test]
+    async fn not_plain_insert() {
+        let err = crate::test::e2e_err(
+            "",
+            "-- @copy
+            PREPARE create_film AS INSERT INTO films(title) VALUES (upper($1));",
+        )
+        .await;
+        insta::assert_snapshot!(err, @"@copy needs an INSERT INTO t (cols) VALUES ($1, ...) writing each parameter once");
+    }
+}
+
+mod bulk {
+    t!(
+        returning,
+        "-- @bulk
+        PREPARE create_films AS INSERT INTO films(title, description, language_id) VALUES ($2, $3, $1)
+        ON CONFLICT DO NOTHING RETURNING film_id, title;"
+    );
+    t!(
+        execute,
+        config = r#"
+            [output]
+            bb8 = true
+        "#,
+        "-- @bulk
+        PREPARE create_languages AS INSERT INTO languages(name) VALUES (:name);"
+    );
+
+    #[tokio::test]
+    async fn not_plain_insert() {
+        let err = crate::test::e2e_err(
+            "",
+            "-- @bulk
+            PREPARE create_film AS INSERT INTO films(title, language_id) VALUES ($1, 1);",
+        )
+        .await;
+        insta::assert_snapshot!(err, @"@bulk needs an INSERT INTO t (cols) VALUES ($1, ...) writing each parameter once");
+    }
+}
+
+mod channel {
+    t!(
+        json_and_text,
+        "-- @channel film_updated: json crate::FilmUpdated
+        PREPARE find_title AS SELECT title FROM films WHERE film_id = $1;
+
+        -- @channel cache_cleared: text"
+    );
+
+    #[tokio::test]
+    async fn duplicated() {
+        let err = crate::test::e2e_err(
+            "",
+            "-- @channel film_updated: text
+            -- @channel film_updated: json crate::FilmUpdated",
+        )
+        .await;
+        insta::assert_snapshot!(err, @"channel film_updated is declared more than once");
+    }
+}
+
+mod constraint_errors {
+    t!(
+        config,
+        config = r#"
+            [output]
+            constraint_errors = true
+        "#,
+        "PREPARE create_studio AS INSERT INTO studios(name) VALUES ($1) RETURNING studio_id;
+        PREPARE create_release AS INSERT INTO releases(studio_id) VALUES ($1);
+        PREPARE delete_studio AS DELETE FROM studios WHERE studio_id = $1;
+        PREPARE list_studios AS SELECT name FROM studios;"
+    );
+}
+
+mod tracing {
+    t!(
+        config,
+        config = r#"
+            [output]
+            tracing = true
+            stream = true
+        "#,
+        "PREPARE list_films AS SELECT film_id, title FROM films WHERE language_id = $1;
+        -- @returns one scalar
+        PREPARE count_films AS SELECT count(*) FROM films;
+        -- @returns opt scalar
+        PREPARE find_title AS SELECT title FROM films WHERE film_id = $1;
+        PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;"
+    );
+    t!(
+        pool,
+        config = r#"
+            [output]
+            tracing = true
+            deadpool = true
+            bb8 = true
+        "#,
+        "PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;"
+    );
+    t!(
+        postgres_typed,
+        config = r#"
+            backend = "postgres"
+
+            [output]
+            tracing = true
+            query_typed = true
+        "#,
+        "PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;"
+    );
+    t!(
+        postgres_stream,
+        config = r#"
+            backend = "postgres"
+
+            [output]
+            tracing = true
+            stream = true
+        "#,
+        "PREPARE list_films AS SELECT film_id, title FROM films WHERE language_id = $1;"
+    );
+    t!(
+        sqlx,
+        config = r#"
+            backend = "sqlx"
+
+            [output]
+            tracing = true
+        "#,
+        "-- @returns scalar
+        PREPARE list_titles AS SELECT title FROM films;
+        PREPARE delete_film AS DELETE FROM films WHERE film_id = $1;"
+    );
+}
+
+mod pool {
+    t!(
+        config,
+        config = r#"
+            [output]
+            deadpool = true
+            bb8 = true
+        "#,
+        crate::test::FILM_STATEMENTS
+    );
+
+    #[test]
+    fn blocking_backend() {
+        let err = crate::config::Config::parse(
+            r#"
+            backend = "postgres"
+
+            [output]
+            deadpool = true
+            "#,
+        )
+        .unwrap_err();
+        insta::assert_snapshot!(err, @"output.deadpool and output.bb8 need the tokio-postgres backend");
+    }
+}
+
 mod insert {
     t!(
         basic,
@@ -215,6 +715,173 @@ mod update {
     );
 }
 
+mod param_naming {
+    t!(
+        not_eq,
+        "PREPARE a AS SELECT title FROM films WHERE film_id <> $1;"
+    );
+    t!(
+        any,
+        "PREPARE a AS SELECT title FROM films WHERE film_id = ANY($1);"
+    );
+    t!(
+        between,
+        "PREPARE a AS SELECT title FROM films WHERE film_id NOT BETWEEN $1 AND $2;"
+    );
+    t!(
+        in_list,
+        "PREPARE a AS SELECT title FROM films WHERE film_id IN ($1, $2) AND language_id NOT IN ($3);"
+    );
+    t!(
+        not_like,
+        "PREPARE a AS SELECT title FROM films WHERE title NOT LIKE $1 OR description ILIKE $2;"
+    );
+    t!(
+        function_argument,
+        "PREPARE a AS SELECT title FROM films WHERE lower(title) = lower($1) AND starts_with(description, $2);"
+    );
+    t!(
+        cast,
+        "PREPARE a AS SELECT title FROM films WHERE film_id = $1::int AND (language_id > $2);"
+    );
+    t!(
+        is_distinct_from,
+        "PREPARE a AS SELECT title FROM films WHERE $1 IS DISTINCT FROM language_id;"
+    );
+    t!(
+        unrelated_predicates,
+        "PREPARE a AS SELECT title FROM films WHERE description IS NULL AND NOT (film_id < $1);"
+    );
+}
+
+mod param_positions {
+    t!(
+        limit_offset,
+        "PREPARE a AS SELECT title FROM films ORDER BY title LIMIT $1 OFFSET $2;"
+    );
+    t!(
+        projection,
+        "PREPARE a AS SELECT $1::text AS greeting, title FROM films;"
+    );
+    t!(
+        order_by,
+        "PREPARE a AS SELECT title FROM films ORDER BY CASE WHEN language_id = $1 THEN 0 ELSE 1 END;"
+    );
+    t!(
+        having,
+        "PREPARE a AS SELECT language_id, count(1) FROM films GROUP BY language_id HAVING count(1) > $1;"
+    );
+    t!(
+        join_on,
+        "PREPARE a AS SELECT f.title FROM films AS f JOIN languages AS l ON l.language_id = f.language_id AND l.name = $1;"
+    );
+    t!(
+        insert_select,
+        "PREPARE a AS INSERT INTO films(title, language_id) SELECT $1, language_id FROM languages WHERE name = $2;"
+    );
+    t!(
+        fallback,
+        "PREPARE a AS SELECT title FROM films WHERE $1 OR film_id = $2;"
+    );
+}
+
+mod param_names {
+    t!(
+        annotated,
+        "-- @param $1 id
+        PREPARE a AS SELECT title FROM films WHERE film_id = $1 AND language_id = $2;"
+    );
+    t!(
+        colon_placeholder,
+        "PREPARE a AS SELECT title FROM films WHERE film_id = :id OR (language_id = :language AND film_id > :id);"
+    );
+    t!(
+        array_placeholder,
+        "PREPARE a AS SELECT title FROM films WHERE film_id = ANY(ARRAY[:first, :second]::int[]) AND (ARRAY[film_id, language_id])[:index] = film_id;"
+    );
+    t!(
+        array_slice,
+        "PREPARE a AS SELECT title FROM films WHERE cardinality((ARRAY[film_id, language_id])[1:language_id]) > 0 AND cardinality((ARRAY[film_id, language_id])[film_id::int:language_id]) > :min;"
+    );
+    t!(
+        at_placeholder,
+        "PREPARE a AS UPDATE films SET title = @title WHERE film_id = @id;"
+    );
+    t!(
+        mixed_placeholders,
+        "PREPARE a AS SELECT title FROM films WHERE film_id = $1 AND title = :title AND description::text = ':not_a_param' LIMIT :limit;"
+    );
+    t!(
+        annotated_nullable,
+        "-- @nullable :description
+        PREPARE a AS UPDATE films SET description = :description WHERE film_id = :id;"
+    );
+    t!(
+        reused_placeholder,
+        "PREPARE a AS SELECT title FROM films WHERE film_id = $1 OR language_id = $1;"
+    );
+    t!(
+        self_join,
+        "PREPARE a AS SELECT f1.title FROM films f1 JOIN films f2 ON f2.language_id = f1.language_id WHERE f1.film_id = $1 AND f2.film_id = $2 AND f1.film_id <> $3;"
+    );
+    t!(
+        derived_collision,
+        "PREPARE a AS SELECT title FROM films WHERE film_id = $1 OR film_id = $2 OR film_id = $3;"
+    );
+    t!(
+        derived_collides_with_explicit,
+        "-- @param $2 eq_film_id
+        PREPARE a AS SELECT title FROM films WHERE film_id = $1 AND language_id = $2;"
+    );
+
+    #[tokio::test]
+    async fn conflicting_explicit_names() {
+        let err = crate::test::e2e_err(
+            "",
+            "-- @param $2 id
+            PREPARE a AS SELECT title FROM films WHERE film_id = $1 AND language_id = $2 AND title = :id;",
+        )
+        .await;
+        insta::assert_snapshot!(err, @"parameters $2 and $3 are both named id");
+    }
+}
+
+mod nullable_params {
+    t!(
+        update_nullable_column,
+        "PREPARE a AS UPDATE films SET description = $2, title = $3 WHERE film_id = $1;"
+    );
+    t!(
+        insert_nullable_column,
+        "PREPARE a AS INSERT INTO films(title, description, language_id) VALUES ($1, $2, $3);"
+    );
+    t!(
+        is_not_distinct_from,
+        "PREPARE a AS SELECT title FROM films WHERE original_language_id IS NOT DISTINCT FROM $1;"
+    );
+    t!(
+        coalesce,
+        "PREPARE a AS UPDATE films SET title = COALESCE($2, title) WHERE film_id = $1;"
+    );
+    t!(
+        annotated,
+        "-- @nullable $1
+         -- @not_null $2
+         PREPARE a AS UPDATE films SET title = $1, description = $2 WHERE film_id = $3;"
+    );
+
+    #[tokio::test]
+    async fn unused_target() {
+        let err = crate::test::e2e_err(
+            "",
+            "-- @nullable $3
+            PREPARE a AS UPDATE films SET description = $2 WHERE film_id = $1;",
+        )
+        .await;
+        insta::assert_snapshot!(err, @"@nullable $3 matches no column or parameter");
+    }
+}
+
 mod delete {
 
     t!(
@@ -228,6 +895,186 @@ mod delete {
     );
 }
 
+mod config {
+    t!(
+        type_override,
+        config = r#"
+            [overrides.types]
+            text = "Box<str>"
+        "#,
+        "PREPARE find_film AS SELECT film_id, title FROM films where title = $1;"
+    );
+
+    t!(
+        column_override,
+        config = r#"
+            [overrides.types]
+            text = "Box<str>"
+
+            [overrides.columns]
+            "films.description" = "crate::Description"
+        "#,
+        "PREPARE update_film AS UPDATE films SET description = $2 WHERE film_id = $1 RETURNING title, description;"
+    );
+
+    t!(
+        naming_and_derives,
+        config = r#"
+            derives = ["Debug", "Clone"]
+
+            [naming]
+            params_suffix = "Args"
+            rows_suffix = "Row"
+
+            [output]
+            header = "// @generated by sqlc"
+        "#,
+        "PREPARE find_film AS SELECT film_id, title FROM films where film_id = $1;"
+    );
+
+    t!(
+        no_derives,
+        config = r#"
+            derives = []
+        "#,
+        "PREPARE find_film AS SELECT film_id, title FROM films where film_id = $1;"
+    );
+
+    t!(
+        serde,
+        config = r#"
+            [serde]
+            serialize = true
+            deserialize = true
+            rename_all = "camelCase"
+        "#,
+        "PREPARE find_film AS SELECT film_id, original_language_id FROM films where film_id = $1;"
+    );
+
+    t!(
+        borrowed_params,
+        config = r#"
+            [params]
+            mode = "borrowed"
+        "#,
+        "PREPARE update_film AS UPDATE films SET title = $1, description = $2 WHERE film_id = ANY($3) RETURNING title;
+        PREPARE update_profile AS UPDATE users SET profile = $2, settings = $3 WHERE user_id = $1;
+        PREPARE find_film AS SELECT title FROM films WHERE film_id = $1;"
+    );
+
+    t!(
+        borrowed_serde,
+        config = r#"
+            [params]
+            mode = "borrowed"
+
+            [serde]
+            serialize = true
+            deserialize = true
+        "#,
+        "PREPARE update_film AS UPDATE films SET title = $1, description = $2 WHERE film_id = ANY($3);
+        PREPARE find_film AS SELECT title FROM films WHERE film_id = $1;"
+    );
+
+    t!(
+        derive_annotation,
+        "-- @derive Eq, Hash
+        PREPARE find_film AS SELECT film_id, title FROM films where film_id = $1;
+        PREPARE list_films AS SELECT film_id, title FROM films;"
+    );
+
+    t!(
+        dropped_derive,
+        "-- @derive -PartialEq
+        PREPARE find_film AS SELECT film_id, title FROM films where film_id = $1;"
+    );
+
+    t!(
+        serde_json,
+        config = r#"
+            [serde]
+            serialize = true
+            deserialize = true
+        "#,
+        "-- @json profile: crate::Profile
+        -- @json settings: crate::Settings
+        PREPARE update_settings AS UPDATE users SET settings = $2 WHERE user_id = $1 RETURNING profile, settings;"
+    );
+}
+
+mod json {
+    t!(
+        untyped,
+        "PREPARE a AS SELECT user_id, profile, settings FROM users;"
+    );
+
+    t!(
+        annotated,
+        "-- @json profile: crate::Profile
+         PREPARE find_profile AS SELECT profile FROM users WHERE user_id = $1;"
+    );
+
+    t!(
+        annotated_param,
+        "-- @json settings: crate::Settings
+         PREPARE create_user AS INSERT INTO users(name, settings) VALUES ($1, $2);"
+    );
+
+    mod operators {
+        t!(arrow, "PREPARE a AS SELECT profile->'address' FROM users;");
+        t!(
+            long_arrow,
+            "PREPARE a AS SELECT user_id, profile->'address'->>'city' AS city FROM users;"
+        );
+        t!(
+            hash_arrow,
+            "PREPARE a AS SELECT settings#>'{theme,colors}', settings#>>'{theme,name}' FROM users;"
+        );
+    }
+
+    mod functions {
+        t!(
+            build_object,
+            "PREPARE a AS SELECT jsonb_build_object('id', user_id, 'name', name) AS user FROM users;"
+        );
+        t!(
+            agg,
+            "PREPARE a AS SELECT json_agg(name), jsonb_agg(user_id) FROM users;"
+        );
+    }
+
+    t!(
+        config,
+        config = r#"
+            [overrides.columns]
+            "users.settings" = "crate::Settings"
+        "#,
+        "PREPARE update_settings AS UPDATE users SET settings = $2 WHERE user_id = $1 RETURNING settings;"
+    );
+
+    #[tokio::test]
+    async fn unused_target() {
+        let err = crate::test::e2e_err(
+            "",
+            "-- @json setting: crate::Settings
+            PREPARE find_settings AS SELECT settings FROM users WHERE user_id = $1;",
+        )
+        .await;
+        insta::assert_snapshot!(err, @"@json setting matches no column or parameter");
+    }
+
+    #[tokio::test]
+    async fn unknown_key() {
+        let err = crate::test::e2e_err(
+            "",
+            "-- @jsno settings: crate::Settings
+            PREPARE find_settings AS SELECT settings FROM users WHERE user_id = $1;",
+        )
+        .await;
+        insta::assert_snapshot!(err, @"unknown annotation @jsno");
+    }
+}
+
 t!(
     multiple_prepare,
     "PREPARE list_films AS SELECT film_id, title FROM films;
@@ -243,7 +1090,9 @@ async fn fill_example() {
     let mut rs = tokio::fs::File::create("./examples/films.rs")
         .await
         .unwrap();
-    translate_file(&t, &mut sql, &mut rs).await.unwrap();
+    translate_file(&t, &Config::default(), &mut sql, &mut rs)
+        .await
+        .unwrap();
     rs.write_all(
         b"\n// The main is not autogenerated, but is needed to example folder to compile\n",
     )